@@ -1,7 +1,150 @@
-#[derive(Clone, Copy)]
-struct Conf {
-    debug: bool,
-    climb0_count: i32,
+// Declares Conf, the registry of every tunable parameter, with its defaults.
+// Each parameter can be overridden, in increasing priority, by a `key = value` line of the
+// file given by `conf=<path>`, by the environment variable AHC045_<KEY>, and by a
+// `key=value` argument. A parameter followed by `=> range` only accepts values in the range.
+macro_rules! params {
+    ($($(#[doc = $doc:expr])* $name:ident: $ty:ty = $default:expr $(=> $range:expr)?,)*) => {
+        #[derive(Clone, Copy)]
+        struct Conf {
+            $($(#[doc = $doc])* $name: $ty,)*
+        }
+
+        impl Conf {
+            fn new() -> Self {
+                Conf { $($name: $default,)* }
+            }
+            fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+                match key {
+                    $(stringify!($name) => {
+                        let parsed: $ty = value.trim().parse()
+                            .map_err(|_| format!("invalid value for {}: {}", key, value))?;
+                        $(if !($range).contains(&parsed) {
+                            return Err(format!("{} must be in {:?}: {}", key, $range, value));
+                        })?
+                        self.$name = parsed;
+                    })*
+                    _ => return Err(format!("unknown parameter: {}", key)),
+                }
+                Ok(())
+            }
+            fn keys() -> &'static [&'static str] {
+                &[$(stringify!($name),)*]
+            }
+            fn dump(&self) {
+                $(eprintln!("conf {} = {}", stringify!($name), self.$name);)*
+            }
+            fn describe(&self) -> String {
+                let items: Vec<String> = vec![$(format!("{}={}", stringify!($name), self.$name),)*];
+                items.join(" ")
+            }
+        }
+    };
+}
+
+params! {
+    /// Print diagnostics to stderr.
+    debug: bool = false,
+    /// Seed of all random number streams.
+    seed: u64 = 0xc0ba_e964,
+    /// Number of swaps tried by climb0.
+    climb0_count: i32 = 0 => 0..,
+    /// Estimated edges for groups left without queries: auto (per group), center or robust.
+    est_edges: EstEdges = EstEdges::Auto,
+    /// Fraction of Q spent by find_edges_by_oracle.
+    query_budget: f64 = 1.0 => 0.0..=1.0,
+    /// Length of the nearest-neighbor lists in SpatialIndex.
+    knn: usize = 10 => 1..,
+    /// Seconds from the start until the last stage must stop.
+    time_limit: f64 = 1.8 => 0.0..=1e6,
+    /// Seconds from the start given to multistart.
+    multistart_time: f64 = 0.6 => 0.0..=1e6,
+    /// Every this many starts, multistart builds a new greedy tour instead of reusing one.
+    multistart_new_tour: usize = 8 => 1..,
+    /// If positive, multistart makes exactly this many starts instead of running until its deadline.
+    multistart_starts: usize = 0,
+    /// If positive, climb_leaves makes exactly this many iterations instead of running until its deadline.
+    climb_leaves_iters: u64 = 0,
+    /// climb_leaves stops after this many iterations in a row without a gain.
+    climb_leaves_patience: u64 = 20000 => 1..,
+    /// climb_leaves only makes a move whose expected gain exceeds this many times the total
+    /// spread of the edges involved, half the width of their possible distance intervals.
+    climb_leaves_margin: f64 = 0.25 => 0.0..=1e6,
+    /// Beam width of init_beam.
+    beam_width: usize = 4 => 1..,
+    /// Branching factor of init_beam.
+    beam_branch: usize = 4 => 1..,
+    /// Isolation penalty of init_beam, in mean nearest-neighbor distances.
+    beam_isolation: f64 = 4.0 => 0.0..=1e6,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EstEdges {
+    Auto,
+    Center,
+    Robust,
+}
+
+impl std::str::FromStr for EstEdges {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "auto" => Ok(EstEdges::Auto),
+            "center" => Ok(EstEdges::Center),
+            "robust" => Ok(EstEdges::Robust),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for EstEdges {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            EstEdges::Auto => "auto",
+            EstEdges::Center => "center",
+            EstEdges::Robust => "robust",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// Applies the config file, the environment and the arguments to the defaults.
+// Returns the remaining arguments, which are not parameters.
+fn load_conf(args: &[String]) -> (Conf, Vec<String>) {
+    let mut conf = Conf::new();
+    let set = |conf: &mut Conf, key: &str, value: &str| {
+        conf.set(key, value).unwrap_or_else(|err| panic!("{}", err));
+    };
+    for arg in args {
+        if let Some(path) = arg.strip_prefix("conf=") {
+            let content = std::fs::read_to_string(path).unwrap_or_else(|_| panic!("no such file: {}", path));
+            for line in content.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (key, value) = line.split_once('=').unwrap_or_else(|| panic!("invalid line: {}", line));
+                set(&mut conf, key.trim(), value);
+            }
+        }
+    }
+    for &key in Conf::keys() {
+        if let Ok(value) = std::env::var(format!("AHC045_{}", key.to_uppercase())) {
+            set(&mut conf, key, &value);
+        }
+    }
+    let mut rest = vec![];
+    for arg in args {
+        if arg == "debug" {
+            conf.debug = true;
+        } else if arg.starts_with("conf=") {
+            continue;
+        } else if let Some((key, value)) = arg.split_once('=').filter(|(key, _)| Conf::keys().contains(key)) {
+            set(&mut conf, key, value);
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    (conf, rest)
 }
 
 fn getline() -> String {
@@ -49,56 +192,448 @@ impl UnionFind {
     }
 }
 
+// Linear congruential generator. Streams with the same seed differ in both the starting state
+// and the increment, so they produce unrelated sequences.
 struct Rng {
     x: u64,
+    inc: u64,
 }
 
 impl Rng {
+    fn new(seed: u64, stream: u64) -> Self {
+        Rng { x: splitmix64(seed ^ splitmix64(stream)), inc: splitmix64(stream) | 1 }
+    }
     fn next(&mut self) -> u32 {
         let a = 0xdead_c0de_0013_3331u64;
-        let b = 2457;
-        self.x = self.x.wrapping_mul(a).wrapping_add(b);
+        self.x = self.x.wrapping_mul(a).wrapping_add(self.inc);
         let x = self.x;
         ((x ^ x << 10) >> 32) as _
     }
 }
 
-fn query(c: &[usize]) -> Vec<(usize, usize)> {
-    print!("? {}", c.len());
-    for &ci in c {
-        print!(" {}", ci);
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// FNV-1a hash of the input and the configuration, which identify a run.
+fn fingerprint(input: &Input, conf: &Conf) -> u64 {
+    let mut h = 0xcbf2_9ce4_8422_2325u64;
+    let mut eat = |v: u64| {
+        for b in v.to_le_bytes() {
+            h = (h ^ b as u64).wrapping_mul(0x100_0000_01b3);
+        }
+    };
+    for v in [input.n, input.m, input.q, input.l, input.w].iter().chain(&input.g)
+        .chain(&input.lx).chain(&input.rx).chain(&input.ly).chain(&input.ry) {
+        eat(*v as u64);
     }
-    println!();
+    for b in conf.describe().bytes() {
+        eat(b as u64);
+    }
+    h
+}
 
-    let mut res = vec![];
-    for _ in 0..c.len() - 1 {
-        let line = getline().trim().to_string();
-        let nums: Vec<usize> = line
-            .split_whitespace()
-            .map(|x| x.parse::<usize>().unwrap())
-            .collect();
-        res.push((nums[0], nums[1]));
+// Uniform grid over estimated positions, with the k nearest neighbors of every city.
+// Call rebuild after the positions are refined.
+struct SpatialIndex {
+    cell: usize,
+    side: usize,
+    cells: Vec<Vec<usize>>,
+    knn: Vec<Vec<usize>>,
+    k: usize,
+}
+
+impl SpatialIndex {
+    fn new(x: &[usize], y: &[usize], k: usize) -> Self {
+        // About two cities per cell.
+        let n = x.len().max(1);
+        let cell = ((10001.0 / (n as f64 / 2.0).sqrt()).ceil() as usize).max(1);
+        let side = 10001 / cell + 1;
+        let mut index = SpatialIndex { cell, side, cells: vec![], knn: vec![], k };
+        index.rebuild(x, y);
+        index
+    }
+    fn rebuild(&mut self, x: &[usize], y: &[usize]) {
+        let n = x.len();
+        self.cells = vec![vec![]; self.side * self.side];
+        for i in 0..n {
+            let c = self.cell_of(x[i], y[i]);
+            self.cells[c].push(i);
+        }
+        self.knn = (0..n).map(|i| self.nearest(x, y, x[i], y[i], self.k, Some(i))).collect();
+    }
+    fn cell_of(&self, x: usize, y: usize) -> usize {
+        (x / self.cell).min(self.side - 1) * self.side + (y / self.cell).min(self.side - 1)
+    }
+    fn knn(&self, c: usize) -> &[usize] {
+        &self.knn[c]
+    }
+    // The k cities nearest to (px, py), excluding `skip`, nearest first.
+    fn nearest(&self, x: &[usize], y: &[usize], px: usize, py: usize, k: usize, skip: Option<usize>) -> Vec<usize> {
+        let (cx, cy) = ((px / self.cell).min(self.side - 1), (py / self.cell).min(self.side - 1));
+        let mut found: Vec<(i64, usize)> = vec![];
+        let mut ring = 0;
+        loop {
+            let lo_x = cx.saturating_sub(ring);
+            let hi_x = (cx + ring).min(self.side - 1);
+            let lo_y = cy.saturating_sub(ring);
+            let hi_y = (cy + ring).min(self.side - 1);
+            for gx in lo_x..=hi_x {
+                for gy in lo_y..=hi_y {
+                    if gx.abs_diff(cx).max(gy.abs_diff(cy)) != ring {
+                        continue;
+                    }
+                    for &i in &self.cells[gx * self.side + gy] {
+                        if Some(i) == skip {
+                            continue;
+                        }
+                        let dx = x[i] as i64 - px as i64;
+                        let dy = y[i] as i64 - py as i64;
+                        found.push((dx * dx + dy * dy, i));
+                    }
+                }
+            }
+            // Cities outside the scanned rings are at least `ring * cell` away.
+            let covered = (ring * self.cell) as i64;
+            let whole = lo_x == 0 && lo_y == 0 && hi_x == self.side - 1 && hi_y == self.side - 1;
+            if whole || (found.len() >= k && {
+                found.sort_unstable();
+                found[k - 1].0 <= covered * covered
+            }) {
+                break;
+            }
+            ring += 1;
+        }
+        found.sort_unstable();
+        found.truncate(k);
+        found.into_iter().map(|(_, i)| i).collect()
     }
-    res
 }
 
-fn answer(groups: &[Vec<usize>], edges: &[Vec<(usize, usize)>]) {
-    println!("!");
-    for (group, edge_set) in groups.iter().zip(edges.iter()) {
-        let mut first = true;
-        for city in group {
-            if !first {
-                print!(" ");
-            } else {
-                first = false;
+#[derive(Clone)]
+struct Input {
+    n: usize,
+    m: usize,
+    q: usize,
+    l: usize,
+    w: usize,
+    g: Vec<usize>,
+    lx: Vec<usize>,
+    rx: Vec<usize>,
+    ly: Vec<usize>,
+    ry: Vec<usize>,
+}
+
+impl Input {
+    // Center of the rectangle of city c, which is its estimated position.
+    fn center(&self, c: usize) -> (usize, usize) {
+        ((self.lx[c] + self.rx[c]) / 2, (self.ly[c] + self.ry[c]) / 2)
+    }
+}
+
+fn parse_nums(line: &str) -> Vec<usize> {
+    line.split_whitespace().map(|x| x.parse().unwrap()).collect()
+}
+
+// Reads the input as the judge sends it, one line per call of `next_line`.
+fn read_input(next_line: &mut dyn FnMut() -> String) -> Input {
+    let first_line = parse_nums(&next_line());
+    let (n, m, q, l, w) = (first_line[0], first_line[1], first_line[2], first_line[3], first_line[4]);
+    let g = parse_nums(&next_line());
+    let (mut lx, mut rx, mut ly, mut ry) = (vec![0; n], vec![0; n], vec![0; n], vec![0; n]);
+    for i in 0..n {
+        let rect = parse_nums(&next_line());
+        lx[i] = rect[0];
+        rx[i] = rect[1];
+        ly[i] = rect[2];
+        ry[i] = rect[3];
+    }
+    Input { n, m, q, l, w, g, lx, rx, ly, ry }
+}
+
+// Whether the groups have sizes g, in order, and partition 0..n.
+fn check_partition(n: usize, g: &[usize], groups: &[Vec<usize>]) -> Result<(), String> {
+    if groups.len() != g.len() {
+        return Err(format!("{} groups for M = {}", groups.len(), g.len()));
+    }
+    let mut seen = vec![false; n];
+    for (k, group) in groups.iter().enumerate() {
+        if group.len() != g[k] {
+            return Err(format!("group {k} has {} cities for G = {}", group.len(), g[k]));
+        }
+        for &c in group {
+            if c >= n || seen[c] {
+                return Err(format!("city {c} is invalid or appears multiple times"));
             }
-            print!("{}", city);
+            seen[c] = true;
+        }
+    }
+    Ok(())
+}
+
+// Whether the edges form a spanning tree of the group.
+fn check_tree(n: usize, group: &[usize], edge_set: &[(usize, usize)]) -> Result<(), String> {
+    if edge_set.len() + 1 != group.len() {
+        return Err(format!("{} edges for {} cities", edge_set.len(), group.len()));
+    }
+    let mut member = vec![false; n];
+    for &c in group {
+        member[c] = true;
+    }
+    let mut uf = UnionFind::new(n);
+    for &(a, b) in edge_set {
+        if a >= n || b >= n || !member[a] || !member[b] || uf.is_same_set(a, b) {
+            return Err(format!("invalid edge: {a} {b}"));
+        }
+        uf.unite(a, b);
+    }
+    Ok(())
+}
+
+// Everything the solver exchanges with the judge.
+trait Oracle {
+    fn input(&self) -> &Input;
+    fn query(&mut self, c: &[usize]) -> Vec<(usize, usize)>;
+    fn answer(&mut self, groups: &[Vec<usize>], edges: &[Vec<(usize, usize)>]);
+}
+
+// The judge on the other side of stdin/stdout.
+struct StdioOracle {
+    input: Input,
+}
+
+impl StdioOracle {
+    fn new() -> Self {
+        StdioOracle { input: read_input(&mut getline) }
+    }
+}
+
+impl Oracle for StdioOracle {
+    fn input(&self) -> &Input {
+        &self.input
+    }
+    fn query(&mut self, c: &[usize]) -> Vec<(usize, usize)> {
+        print!("? {}", c.len());
+        for &ci in c {
+            print!(" {}", ci);
         }
         println!();
-        for &(a, b) in edge_set {
-            println!("{} {}", a, b);
+
+        let mut res = vec![];
+        for _ in 0..c.len() - 1 {
+            let nums = parse_nums(&getline());
+            res.push((nums[0], nums[1]));
+        }
+        res
+    }
+    fn answer(&mut self, groups: &[Vec<usize>], edges: &[Vec<(usize, usize)>]) {
+        println!("!");
+        for (group, edge_set) in groups.iter().zip(edges.iter()) {
+            let mut first = true;
+            for city in group {
+                if !first {
+                    print!(" ");
+                } else {
+                    first = false;
+                }
+                print!("{}", city);
+            }
+            println!();
+            for &(a, b) in edge_set {
+                println!("{} {}", a, b);
+            }
+        }
+    }
+}
+
+// A JSON string literal holding s.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_list(v: &[usize]) -> String {
+    let items: Vec<String> = v.iter().map(|e| e.to_string()).collect();
+    format!("[{}]", items.join(","))
+}
+
+// Structured dump of the solver state for visualization, enabled by `dump=<path>`
+// (`dump=-` for stderr). One JSON object per line, distinguished by "type":
+// - {"type":"input","file":..,"n":..,"m":..,"q":..,"l":..,"w":..,"g":[..]} starts a run
+// - {"type":"city","id":..,"x":..,"y":..,"lx":..,"rx":..,"ly":..,"ry":..} is the belief about a
+//   city: its estimated position and the rectangle it is known to lie in
+// - {"type":"query","id":..,"cities":[..],"edges":[[a,b],..]} is a query and its answer
+// - {"type":"group","stage":..,"id":..,"cities":[..]} is a group after a stage; the final stage
+//   adds "edges":[[a,b,source],..] where source is "queried" or "estimated"
+struct Dump {
+    out: Option<Box<dyn std::io::Write>>,
+    queried: std::collections::HashSet<(usize, usize)>,
+    queries: usize,
+}
+
+impl Dump {
+    fn new(path: Option<&str>) -> Self {
+        let out: Option<Box<dyn std::io::Write>> = match path {
+            None => None,
+            Some("-") => Some(Box::new(std::io::stderr())),
+            Some(path) => Some(Box::new(std::io::BufWriter::new(
+                std::fs::File::create(path).unwrap_or_else(|_| panic!("cannot create {}", path)),
+            ))),
+        };
+        Dump { out, queried: Default::default(), queries: 0 }
+    }
+    fn line(&mut self, line: String) {
+        if let Some(out) = self.out.as_mut() {
+            writeln!(out, "{}", line).unwrap();
         }
     }
+    fn input(&mut self, file: &str, input: &Input, x: &[usize], y: &[usize]) {
+        if self.out.is_none() {
+            return;
+        }
+        self.queried.clear();
+        self.queries = 0;
+        self.line(format!(
+            "{{\"type\":\"input\",\"file\":{},\"n\":{},\"m\":{},\"q\":{},\"l\":{},\"w\":{},\"g\":{}}}",
+            json_string(file), input.n, input.m, input.q, input.l, input.w, json_list(&input.g),
+        ));
+        for i in 0..input.n {
+            self.line(format!(
+                "{{\"type\":\"city\",\"id\":{},\"x\":{},\"y\":{},\"lx\":{},\"rx\":{},\"ly\":{},\"ry\":{}}}",
+                i, x[i], y[i], input.lx[i], input.rx[i], input.ly[i], input.ry[i],
+            ));
+        }
+    }
+    fn query(&mut self, c: &[usize], res: &[(usize, usize)]) {
+        for &(a, b) in res {
+            self.queried.insert((a.min(b), a.max(b)));
+        }
+        if self.out.is_none() {
+            return;
+        }
+        let edges: Vec<String> = res.iter().map(|&(a, b)| format!("[{},{}]", a, b)).collect();
+        self.line(format!(
+            "{{\"type\":\"query\",\"id\":{},\"cities\":{},\"edges\":[{}]}}",
+            self.queries, json_list(c), edges.join(","),
+        ));
+        self.queries += 1;
+    }
+    fn groups(&mut self, stage: &str, groups: &[Vec<usize>], edges: Option<&[Vec<(usize, usize)>]>) {
+        if self.out.is_none() {
+            return;
+        }
+        for (k, group) in groups.iter().enumerate() {
+            let mut line = format!(
+                "{{\"type\":\"group\",\"stage\":{},\"id\":{},\"cities\":{}",
+                json_string(stage), k, json_list(group),
+            );
+            if let Some(edges) = edges {
+                let edges: Vec<String> = edges[k].iter().map(|&(a, b)| {
+                    let source = if self.queried.contains(&(a.min(b), a.max(b))) { "queried" } else { "estimated" };
+                    format!("[{},{},\"{}\"]", a, b, source)
+                }).collect();
+                line += &format!(",\"edges\":[{}]", edges.join(","));
+            }
+            line.push('}');
+            self.line(line);
+        }
+    }
+}
+
+// Passes queries through to `inner` and records them in the dump.
+struct DumpOracle<'a> {
+    inner: &'a mut dyn Oracle,
+    dump: &'a mut Dump,
+}
+
+impl Oracle for DumpOracle<'_> {
+    fn input(&self) -> &Input {
+        self.inner.input()
+    }
+    fn query(&mut self, c: &[usize]) -> Vec<(usize, usize)> {
+        let res = self.inner.query(c);
+        self.dump.query(c, &res);
+        res
+    }
+    fn answer(&mut self, groups: &[Vec<usize>], edges: &[Vec<(usize, usize)>]) {
+        self.inner.answer(groups, edges);
+    }
+}
+
+// An in-process judge over a generated input file, which also contains the true positions.
+// Queries are answered with local_mst, which matches the judge exactly.
+struct LocalOracle {
+    input: Input,
+    x: Vec<usize>,
+    y: Vec<usize>,
+    queries: usize,
+    // Score of the answer, or the reason it would be rejected.
+    score: Option<Result<usize, String>>,
+}
+
+impl LocalOracle {
+    fn new(content: &str) -> Self {
+        let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+        let mut next_line = || lines.next().unwrap().to_string();
+        let input = read_input(&mut next_line);
+        let (mut x, mut y) = (vec![0; input.n], vec![0; input.n]);
+        for i in 0..input.n {
+            let xy = parse_nums(&next_line());
+            x[i] = xy[0];
+            y[i] = xy[1];
+        }
+        LocalOracle { input, x, y, queries: 0, score: None }
+    }
+    fn score(&self, groups: &[Vec<usize>], edges: &[Vec<(usize, usize)>]) -> Result<usize, String> {
+        let input = &self.input;
+        check_partition(input.n, &input.g, groups)?;
+        if edges.len() != input.m {
+            return Err(format!("{} edge sets for M = {}", edges.len(), input.m));
+        }
+        let mut score = 0;
+        for (k, (group, edge_set)) in groups.iter().zip(edges).enumerate() {
+            check_tree(input.n, group, edge_set).map_err(|err| format!("group {k}: {err}"))?;
+            for &(a, b) in edge_set {
+                score += judge_dist(&self.x, &self.y, a, b);
+            }
+        }
+        Ok(score)
+    }
+}
+
+impl Oracle for LocalOracle {
+    fn input(&self) -> &Input {
+        &self.input
+    }
+    fn query(&mut self, c: &[usize]) -> Vec<(usize, usize)> {
+        self.queries += 1;
+        assert!(self.queries <= self.input.q, "too many queries");
+        assert!(2 <= c.len() && c.len() <= self.input.l, "query of size {}", c.len());
+        let mut sorted = c.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert!(sorted.len() == c.len() && sorted[sorted.len() - 1] < self.input.n, "invalid query: {:?}", c);
+        local_mst(c, &self.x, &self.y)
+    }
+    fn answer(&mut self, groups: &[Vec<usize>], edges: &[Vec<(usize, usize)>]) {
+        self.score = Some(self.score(groups, edges));
+    }
 }
 
 fn init_dist(x: &[usize], y: &[usize], cities: &[usize]) -> f64 {
@@ -136,43 +671,346 @@ fn init_mo(x: &[usize], y: &[usize], w: usize) -> Vec<usize> {
     cities
 }
 
-fn init_greedy(x: &[usize], y: &[usize], _w: usize) -> Vec<usize> {
-    let mut cities = vec![0];
-    let mut rem: Vec<_> = (1..x.len()).collect();
+// Cheapest insertion, starting from city `first`.
+// Every remaining city keeps its best slot (the front of the tour, or right after a tour city).
+// An insertion changes only the slot it consumes and the new slot after the inserted city, so
+// only the cities near the inserted one (its kNN lists in `index`, both ways) try the new
+// slots, and the cities whose best slot was consumed look for a new one next to their
+// nearest tour cities. The next city comes from a priority queue with lazily discarded entries.
+fn init_greedy(x: &[usize], y: &[usize], index: &SpatialIndex, first: usize) -> Vec<usize> {
+    use std::cmp::Ordering;
+    use std::collections::BinaryHeap;
+    #[derive(PartialEq)]
+    struct Entry(f64, usize, u32);
+    impl Eq for Entry {}
+    impl PartialOrd for Entry {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+    impl Ord for Entry {
+        // Reversed so that BinaryHeap pops the cheapest insertion first.
+        fn cmp(&self, other: &Self) -> Ordering {
+            other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+        }
+    }
+    const NIL: usize = usize::MAX;
+    let n = x.len();
+    let front = n;
     let dist = |a: usize, b: usize| {
         ((x[a] as f64 - x[b] as f64).powi(2) + (y[a] as f64 - y[b] as f64).powi(2)).sqrt()
     };
-    while !rem.is_empty() {
-        let mut best_dist = f64::MAX;
-        let mut best_i = 0;
-        let mut best_j = 0;
-        for i in 0..=cities.len() {
-            for j in 0..rem.len() {
-                let mut diff = 0.0;
-                if i < cities.len() {
-                    diff += dist(cities[i], rem[j]);
+    let (mut next, mut prev) = (vec![NIL; n], vec![NIL; n]);
+    let mut head = first;
+    // Cost of inserting c into slot s, where slot u < n means right after u.
+    let slot_cost = |next: &[usize], head: usize, s: usize, c: usize| {
+        if s == front {
+            return dist(head, c);
+        }
+        let nx = next[s];
+        if nx == NIL {
+            dist(s, c)
+        } else {
+            dist(nx, c) + dist(s, c) - dist(s, nx)
+        }
+    };
+    // Best slot of d among the front and the slots on both sides of its nearest tour cities,
+    // or among all slots while none of its nearest cities is in the tour.
+    let rescan = |next: &[usize], prev: &[usize], head: usize, done: &[bool], d: usize| {
+        let mut best = (slot_cost(next, head, front, d), front);
+        let mut consider = |s: usize| {
+            let cost = slot_cost(next, head, s, d);
+            if cost < best.0 {
+                best = (cost, s);
+            }
+        };
+        let near: Vec<usize> = index.knn(d).iter().cloned().filter(|&v| done[v]).collect();
+        if near.is_empty() {
+            let mut u = head;
+            while u != NIL {
+                consider(u);
+                u = next[u];
+            }
+        }
+        for v in near {
+            consider(v);
+            if prev[v] != NIL {
+                consider(prev[v]);
+            }
+        }
+        best
+    };
+    let mut rknn = vec![vec![]; n];
+    for d in 0..n {
+        for &c in index.knn(d) {
+            rknn[c].push(d);
+        }
+    }
+    let mut best = vec![(f64::MAX, front); n];
+    // Cities that took each slot as their best, possibly since moved elsewhere.
+    let mut holders = vec![vec![]; n + 1];
+    let mut stamp = vec![0u32; n];
+    let mut done = vec![false; n];
+    done[first] = true;
+    let mut que = BinaryHeap::new();
+    for c in (0..n).filter(|&c| c != first) {
+        best[c] = rescan(&next, &prev, head, &done, c);
+        holders[best[c].1].push(c);
+        que.push(Entry(best[c].0, c, 0));
+    }
+    for _ in 1..n {
+        let c = loop {
+            let Entry(_, c, st) = que.pop().unwrap();
+            if !done[c] && stamp[c] == st {
+                break c;
+            }
+        };
+        done[c] = true;
+        let s = best[c].1;
+        if s == front {
+            next[c] = head;
+            prev[head] = c;
+            head = c;
+        } else {
+            next[c] = next[s];
+            if next[s] != NIL {
+                prev[next[s]] = c;
+            }
+            next[s] = c;
+            prev[c] = s;
+        }
+        for d in std::mem::take(&mut holders[s]) {
+            if !done[d] && best[d].1 == s {
+                best[d] = rescan(&next, &prev, head, &done, d);
+                holders[best[d].1].push(d);
+                stamp[d] += 1;
+                que.push(Entry(best[d].0, d, stamp[d]));
+            }
+        }
+        for &d in index.knn(c).iter().chain(&rknn[c]) {
+            if done[d] {
+                continue;
+            }
+            let old = best[d];
+            for t in [s, c] {
+                let cost = slot_cost(&next, head, t, d);
+                if cost < best[d].0 {
+                    best[d] = (cost, t);
+                }
+            }
+            if best[d] != old {
+                holders[best[d].1].push(d);
+                stamp[d] += 1;
+                que.push(Entry(best[d].0, d, stamp[d]));
+            }
+        }
+    }
+    let mut cities = Vec::with_capacity(n);
+    let mut u = head;
+    while u != NIL {
+        cities.push(u);
+        u = next[u];
+    }
+    cities
+}
+
+
+// Grows the groups one at a time, largest first, as compact regions. Each group starts from
+// the most isolated remaining city and is extended by beam search: a state adds one of the
+// remaining cities closest to it (Prim's rule, so the cost is the MST cost of the region),
+// and is scored by that cost plus a penalty for every remaining neighbor it leaves with no
+// remaining neighbors of its own. Returns groups in the order of g, each in growth order.
+fn init_beam(
+    conf: Conf, x: &[usize], y: &[usize], g: &[usize],
+    index: &SpatialIndex, rng: &mut Rng,
+) -> Vec<Vec<usize>> {
+    #[derive(Clone)]
+    struct State {
+        members: Vec<usize>,
+        in_set: Vec<bool>,
+        // Distance from each city to the nearest member.
+        link: Vec<f64>,
+        cost: f64,
+        score: f64,
+        hash: u64,
+    }
+    let n = x.len();
+    let dist = |a: usize, b: usize| {
+        ((x[a] as f64 - x[b] as f64).powi(2) + (y[a] as f64 - y[b] as f64).powi(2)).sqrt()
+    };
+    let zobrist: Vec<u64> = (0..n).map(|_| (rng.next() as u64) << 32 | rng.next() as u64).collect();
+    let nn_dist = (0..n)
+        .filter_map(|c| index.knn(c).first().map(|&d| dist(c, d)))
+        .sum::<f64>() / n as f64;
+    let penalty = conf.beam_isolation * nn_dist;
+    let mut taken = vec![false; n];
+    let mut order: Vec<usize> = (0..g.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(g[i]));
+    let mut groups = vec![vec![]; g.len()];
+    for &gi in &order {
+        let free_neighbors = |c: usize, taken: &[bool]| index.knn(c).iter().filter(|&&d| !taken[d]).count();
+        let seed = (0..n)
+            .filter(|&c| !taken[c])
+            .min_by_key(|&c| (free_neighbors(c, &taken), x[c], y[c]))
+            .unwrap();
+        let mut init = State {
+            members: vec![seed],
+            in_set: vec![false; n],
+            link: vec![f64::MAX; n],
+            cost: 0.0,
+            score: 0.0,
+            hash: zobrist[seed],
+        };
+        init.in_set[seed] = true;
+        for c in 0..n {
+            if !taken[c] && c != seed {
+                init.link[c] = dist(seed, c);
+            }
+        }
+        let mut beam = vec![init];
+        for _ in 1..g[gi] {
+            let mut children: Vec<(f64, usize, usize, u64)> = vec![];
+            for (si, st) in beam.iter().enumerate() {
+                let mut cand: Vec<(f64, usize)> = (0..n)
+                    .filter(|&c| !taken[c] && !st.in_set[c])
+                    .map(|c| (st.link[c], c))
+                    .collect();
+                let k = conf.beam_branch.min(cand.len());
+                cand.select_nth_unstable_by(k - 1, |a, b| a.partial_cmp(b).unwrap());
+                for &(l, c) in &cand[..k] {
+                    let isolated = index.knn(c).iter()
+                        .filter(|&&u| !taken[u] && !st.in_set[u] && u != c)
+                        .filter(|&&u| index.knn(u).iter().all(|&v| taken[v] || st.in_set[v] || v == c))
+                        .count();
+                    children.push((st.cost + l + penalty * isolated as f64, si, c, st.hash ^ zobrist[c]));
                 }
-                if i > 0 {
-                    diff += dist(cities[i - 1], rem[j]);
+            }
+            children.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            children.dedup_by_key(|ch| ch.3);
+            let mut seen = vec![];
+            let mut next = vec![];
+            for &(score, si, c, hash) in &children {
+                if next.len() >= conf.beam_width {
+                    break;
                 }
-                if i > 0 && i < cities.len() {
-                    diff -= dist(cities[i - 1], cities[i]);
+                if seen.contains(&hash) {
+                    continue;
                 }
-                if best_dist > diff {
-                    best_dist = diff;
-                    best_i = i;
-                    best_j = j;
+                seen.push(hash);
+                let mut st = beam[si].clone();
+                st.cost += st.link[c];
+                st.score = score;
+                st.hash = hash;
+                st.members.push(c);
+                st.in_set[c] = true;
+                for d in 0..n {
+                    if !taken[d] && !st.in_set[d] {
+                        let dd = dist(c, d);
+                        if dd < st.link[d] {
+                            st.link[d] = dd;
+                        }
+                    }
                 }
+                next.push(st);
             }
+            beam = next;
+        }
+        let best = beam.into_iter().min_by(|a, b| a.score.partial_cmp(&b.score).unwrap()).unwrap();
+        for &c in &best.members {
+            taken[c] = true;
         }
-        cities.insert(best_i, rem[best_j]);
-        rem.remove(best_j);
+        groups[gi] = best.members;
     }
-    cities
+    groups
 }
 
+// Cuts a tour into consecutive chunks of sizes g.
+fn split_tour(cities: &[usize], g: &[usize]) -> Vec<Vec<usize>> {
+    let mut groups = Vec::new();
+    let mut start_idx = 0;
+    for &group_size in g {
+        groups.push(cities[start_idx..start_idx + group_size].to_vec());
+        start_idx += group_size;
+    }
+    groups
+}
+
+// Estimated score of a grouping: the sum of the group MSTs over the estimated positions.
+fn groups_mst_cost(x: &[usize], y: &[usize], groups: &[Vec<usize>]) -> f64 {
+    groups.iter().map(|group| delaunay_mst(group, x, y).0).sum()
+}
+
+// Builds candidate groupings from several tours and keeps the one with the smallest
+// estimated score. The base tours come from init_mo and init_greedy; further candidates
+// reverse and rotate them, or run init_greedy from a random first city, until `deadline`.
+// The grouping from init_beam competes as well.
+fn multistart(
+    conf: Conf, input: &Input, x: &[usize], y: &[usize],
+    index: &SpatialIndex, rng: &mut Rng, deadline: std::time::Instant,
+) -> (Vec<Vec<usize>>, usize) {
+    let (w, g) = (input.w, &input.g[..]);
+    let n = x.len();
+    let cities0: Vec<usize> = init_mo(x, y, w);
+    let dist0 = init_dist(x, y, &cities0);
+    eprintln!("dist0 = {dist0}");
+    let cities1 = init_greedy(x, y, index, 0);
+    let dist1 = init_dist(x, y, &cities1);
+    eprintln!("dist1 = {dist1}");
+    // Members of the beam groups follow the greedy tour, so that consecutive cities are close.
+    let mut rank = vec![0; n];
+    for (i, &c) in cities1.iter().enumerate() {
+        rank[c] = i;
+    }
+    let mut beam = init_beam(conf, x, y, g, index, rng);
+    for group in beam.iter_mut() {
+        group.sort_unstable_by_key(|&c| rank[c]);
+    }
+    let beam_cost = groups_mst_cost(x, y, &beam);
+    let mut tours = vec![cities0, cities1];
+    let mut best = (f64::MAX, vec![]);
+    let mut starts = 0;
+    loop {
+        let mut cities = if starts < 2 * tours.len() {
+            tours[starts / 2].clone()
+        } else if starts % conf.multistart_new_tour == 0 {
+            let tour = init_greedy(x, y, index, rng.next() as usize % n);
+            tours.push(tour.clone());
+            tour
+        } else {
+            let mut tour = tours[rng.next() as usize % tours.len()].clone();
+            tour.rotate_left(rng.next() as usize % n);
+            tour
+        };
+        if starts % 2 == 1 {
+            cities.reverse();
+        }
+        let groups = split_tour(&cities, g);
+        let cost = groups_mst_cost(x, y, &groups);
+        if cost < best.0 {
+            if conf.debug {
+                eprintln!("multistart: start {starts}: {} -> {cost}", best.0);
+            }
+            best = (cost, groups);
+        }
+        starts += 1;
+        let done = if conf.multistart_starts > 0 {
+            starts >= conf.multistart_starts
+        } else {
+            std::time::Instant::now() >= deadline
+        };
+        if done {
+            break;
+        }
+    }
+    if conf.debug {
+        eprintln!("multistart: {starts} starts, {} tours, beam: {beam_cost}", tours.len());
+    }
+    if beam_cost < best.0 {
+        best = (beam_cost, beam);
+    }
+    (best.1, starts)
+}
 
-const CLIMB0_COUNT: i32 = 0;
 
 fn score0(x: &[usize], y: &[usize], groups: &[Vec<usize>]) -> f64 {
     let mut score = 0.0;
@@ -210,17 +1048,26 @@ fn score0_diff(
     score
 }
 
-fn climb0(conf: Conf, x: &[usize], y: &[usize], rng: &mut Rng, groups: &mut [Vec<usize>]) {
+fn climb0(conf: Conf, x: &[usize], y: &[usize], index: &SpatialIndex, rng: &mut Rng, groups: &mut [Vec<usize>]) {
     let mut score = score0(x, y, groups);
+    let mut pos = vec![(0, 0); x.len()];
+    for (i, group) in groups.iter().enumerate() {
+        for (ii, &c) in group.iter().enumerate() {
+            pos[c] = (i, ii);
+        }
+    }
     for _ in 0..conf.climb0_count {
-        let i = rng.next() as usize % groups.len();
-        let j = rng.next() as usize % groups.len();
+        let a = rng.next() as usize % x.len();
+        let near = index.knn(a);
+        if near.is_empty() {
+            continue;
+        }
+        let b = near[rng.next() as usize % near.len()];
+        let ((i, ii), (j, jj)) = (pos[a], pos[b]);
         if i == j {
             continue;
         }
         if groups[i].len() > 1 && groups[j].len() > 1 {
-            let ii = rng.next() as usize % groups[i].len();
-            let jj = rng.next() as usize % groups[j].len();
             let tmp = groups[i][ii];
             groups[i][ii] = groups[j][jj];
             groups[j][jj] = tmp;
@@ -235,135 +1082,830 @@ fn climb0(conf: Conf, x: &[usize], y: &[usize], rng: &mut Rng, groups: &mut [Vec
                 if conf.debug {
                     eprintln!("score improvement: {score} -> {new_score}");
                 }
+                pos[a] = (j, jj);
+                pos[b] = (i, ii);
                 score = new_score;
             }
         }
     }
 }
 
+// Windows [start, end) of a group of the given size that find_edges_by_oracle may query:
+// consecutive windows of l cities sharing one city. A trailing pair is left out.
+fn oracle_windows(group_size: usize, l: usize) -> Vec<(usize, usize)> {
+    let mut windows = vec![];
+    let mut i = 0;
+    while i + 2 < group_size {
+        windows.push((i, group_size.min(i + l)));
+        i = group_size.min(i + l - 1);
+    }
+    windows
+}
+
+// Blocks of positions of a group of the given size, larger than l >= 4, such that every pair
+// of cities shares a block: the group is cut into chunks of l / 2 cities, and each block is
+// the union of two chunks.
+fn certify_blocks(group_size: usize, l: usize) -> Vec<Vec<usize>> {
+    let chunks: Vec<(usize, usize)> = (0..group_size).step_by(l / 2)
+        .map(|s| (s, group_size.min(s + l / 2))).collect();
+    let mut blocks = vec![];
+    for (i, &(s, e)) in chunks.iter().enumerate() {
+        for &(t, f) in &chunks[i + 1..] {
+            blocks.push((s..e).chain(t..f).collect());
+        }
+    }
+    blocks
+}
+
+// The cities of a queried block and the MST the oracle answered for them.
+type BlockAnswer = (Vec<usize>, Vec<(usize, usize)>);
+
+// Spanning tree of a group from the answers to certify_blocks, as (cities, MST) pairs.
+// An edge missing from the MST of a block that holds both its ends is on a cycle of shorter
+// edges, so it is not in the MST of the group; since every pair shares a block, the edges
+// that are in the MST of every block holding them contain the MST of the group. When they
+// form a tree, it is the exact MST. Otherwise Kruskal picks among them by expected length.
+// Returns the tree and whether it is exact.
+fn certify(input: &Input, group: &[usize], answers: &[BlockAnswer]) -> (Vec<(usize, usize)>, bool) {
+    use std::collections::HashSet;
+    let key = |a: usize, b: usize| (a.min(b), a.max(b));
+    let sets: Vec<(HashSet<_>, HashSet<_>)> = answers.iter()
+        .map(|(cities, mst)| (cities.iter().cloned().collect(), mst.iter().map(|&(a, b)| key(a, b)).collect()))
+        .collect();
+    let union: HashSet<(usize, usize)> = sets.iter().flat_map(|(_, mst)| mst.iter().cloned()).collect();
+    // Survivors first, then the rest of the union so that the result always spans the group.
+    let mut cand: Vec<(bool, f64, usize, usize)> = union.into_iter().map(|(a, b)| {
+        let kept = sets.iter().all(|(cities, mst)| !(cities.contains(&a) && cities.contains(&b)) || mst.contains(&(a, b)));
+        (!kept, edge_cost(input, a, b).1, a, b)
+    }).collect();
+    cand.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let exact = cand.iter().filter(|c| !c.0).count() + 1 == group.len();
+    let mut local = std::collections::HashMap::new();
+    for (i, &c) in group.iter().enumerate() {
+        local.insert(c, i);
+    }
+    let mut uf = UnionFind::new(group.len());
+    let mut edges = Vec::with_capacity(group.len().saturating_sub(1));
+    for (_, _, a, b) in cand {
+        let (i, j) = (local[&a], local[&b]);
+        if !uf.is_same_set(i, j) {
+            uf.unite(i, j);
+            edges.push((a, b));
+        }
+    }
+    (edges, exact)
+}
+
+// Chooses, group by group, between the estimated edges, a chain of queried windows, a mix
+// of both and a certified tree, by expected cost under the query budget `q`.
+// The expected error of an estimated edge is half the width of its possible distance
+// interval. A window query replaces the estimated edges inside the window with the true MST
+// of the window, so its value is the total error of those edges. Windows are queried by
+// decreasing value until the budget runs out, and stitch_windows joins the answers of a
+// group with its estimated edges `est`.
+// Budget left after every window is planned certifies groups instead (see certify), the
+// groups with the largest error left between their windows per extra query first.
 fn find_edges_by_oracle(
-    groups: &[Vec<usize>], _x: &[usize], _y: &[usize],
-    l: usize,
+    conf: Conf, oracle: &mut dyn Oracle, input: &Input,
+    groups: &[Vec<usize>], q: usize, est: &[Vec<(usize, usize)>],
 ) -> Vec<Vec<(usize, usize)>> {
-    let mut edges = Vec::new();
+    let (n, l) = (input.n, input.l);
+    let mut pos = vec![0; n];
     for group in groups {
-        let mut group_edges = Vec::new();
-        let group_size = group.len();
-        let mut i = 0;
-        while i + 1 < group_size {
-            if i + 2 < group_size {
-                let ret = query(&group[i..group_size.min(i + l)]);
-                group_edges.extend(ret);
-                i = group_size.min(i + l - 1);
-            } else {
-                group_edges.push((group[i], group[i + 1]));
-                i += 2;
+        for (i, &c) in group.iter().enumerate() {
+            pos[c] = i;
+        }
+    }
+    let mut plan = vec![];
+    for (k, group) in groups.iter().enumerate() {
+        let windows = oracle_windows(group.len(), l);
+        let mut value = vec![0.0; windows.len()];
+        for &(a, b) in &est[k] {
+            let (lo, hi) = rect_dist_range(input, a, b);
+            let (pa, pb) = (pos[a].min(pos[b]), pos[a].max(pos[b]));
+            // Windows are sorted and overlap in one city, so at most two contain both ends.
+            for (wi, &(s, e)) in windows.iter().enumerate() {
+                if s <= pa && pb < e {
+                    value[wi] += (hi - lo) / 2.0;
+                }
             }
         }
-        edges.push(group_edges);
+        for (wi, &(s, e)) in windows.iter().enumerate() {
+            if value[wi] > 0.0 {
+                plan.push((value[wi], k, s, e));
+            }
+        }
+    }
+    plan.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    plan.truncate(q);
+    plan.sort_by_key(|&(_, k, s, _)| (k, s));
+
+    let mut left = q - plan.len();
+    let mut upgrades = vec![];
+    for (k, group) in groups.iter().enumerate() {
+        // With l < 4 the blocks are pairs, whose answers say nothing.
+        if group.len() <= l || l < 4 || left == 0 {
+            continue;
+        }
+        let windows: Vec<(usize, usize)> = plan.iter().filter(|p| p.1 == k).map(|p| (p.2, p.3)).collect();
+        let residual: f64 = est[k].iter().filter(|&&(a, b)| {
+            let (pa, pb) = (pos[a].min(pos[b]), pos[a].max(pos[b]));
+            !windows.iter().any(|&(s, e)| s <= pa && pb < e)
+        }).map(|&(a, b)| {
+            let (lo, hi) = rect_dist_range(input, a, b);
+            (hi - lo) / 2.0
+        }).sum();
+        let extra = certify_blocks(group.len(), l).len().saturating_sub(windows.len());
+        if residual > 0.0 {
+            upgrades.push((residual / extra.max(1) as f64, extra, k));
+        }
+    }
+    upgrades.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    let mut certified = vec![false; groups.len()];
+    for (_, extra, k) in upgrades {
+        if extra <= left {
+            left -= extra;
+            certified[k] = true;
+        }
+    }
+    plan.retain(|p| !certified[p.1]);
+
+    let mut edges = vec![];
+    let mut plan = plan.into_iter().peekable();
+    let (mut by_est, mut by_windows, mut mixed, mut by_certify, mut exact) = (0, 0, 0, 0, 0);
+    for (k, group) in groups.iter().enumerate() {
+        if certified[k] {
+            let mut answers = vec![];
+            for block in certify_blocks(group.len(), l) {
+                let cities: Vec<usize> = block.iter().map(|&i| group[i]).collect();
+                let mst = oracle.query(&cities);
+                answers.push((cities, mst));
+            }
+            let (tree, is_exact) = certify(input, group, &answers);
+            by_certify += 1;
+            exact += is_exact as usize;
+            edges.push(tree);
+            continue;
+        }
+        let mut queried = vec![];
+        let mut windows = vec![];
+        while let Some(&(_, _, s, e)) = plan.peek().filter(|p| p.1 == k) {
+            plan.next();
+            queried.extend(oracle.query(&group[s..e]));
+            windows.push((s, e));
+        }
+        let count = windows.len();
+        let all = oracle_windows(group.len(), l).len();
+        match count {
+            0 => by_est += 1,
+            _ if count == all => by_windows += 1,
+            _ => mixed += 1,
+        }
+        if count == 0 {
+            edges.push(est[k].clone());
+        } else {
+            edges.push(stitch_windows(input, group, &windows, &queried, &est[k]));
+        }
+    }
+    if conf.debug {
+        eprintln!("find_edges_by_oracle: groups by estimation = {by_est}, by windows = {by_windows}, mixed = {mixed}, certified = {by_certify} ({exact} exact)");
+    }
+    edges
+}
+
+// Spanning tree of a group from the answers to the queried `windows` (as [start, end) ranges
+// of the group) and estimated candidates: the estimated tree `est` plus the Delaunay edges
+// over the estimated positions. Candidates with both ends in a queried window are dropped,
+// since the answer for that window already decides them.
+// Kruskal runs on one ordering in which a queried edge weighs its lower bound and a
+// candidate its expected length, so a candidate replaces a queried edge only if it is very
+// likely shorter. The chain of windows is a spanning tree of the same graph, so the result is
+// no longer than the chain under these weights; for the true lengths this is only likely.
+// Weighting candidates by their upper bound would guarantee it, but scores worse.
+fn stitch_windows(
+    input: &Input, group: &[usize], windows: &[(usize, usize)],
+    queried: &[(usize, usize)], est: &[(usize, usize)],
+) -> Vec<(usize, usize)> {
+    let k = group.len();
+    let mut local = std::collections::HashMap::new();
+    for (i, &c) in group.iter().enumerate() {
+        local.insert(c, i);
+    }
+    let same_window = |a: usize, b: usize| {
+        let (pa, pb) = (local[&a].min(local[&b]), local[&a].max(local[&b]));
+        windows.iter().any(|&(s, e)| s <= pa && pb < e)
+    };
+    let mut cand: Vec<(f64, usize, usize)> = vec![];
+    for &(a, b) in queried {
+        cand.push((edge_cost(input, a, b).0, a, b));
+    }
+    let pts: Vec<(i64, i64)> = group.iter().map(|&c| input.center(c)).map(|(x, y)| (x as i64, y as i64)).collect();
+    let delaunay = delaunay_edges(&pts).into_iter().map(|(i, j)| (group[i], group[j]));
+    for (a, b) in est.iter().cloned().chain(delaunay) {
+        if !same_window(a, b) {
+            cand.push((edge_cost(input, a, b).1, a, b));
+        }
+    }
+    cand.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut uf = UnionFind::new(k);
+    let mut edges = Vec::with_capacity(k.saturating_sub(1));
+    for (_, a, b) in cand {
+        let (i, j) = (local[&a], local[&b]);
+        if !uf.is_same_set(i, j) {
+            uf.unite(i, j);
+            edges.push((a, b));
+        }
     }
     edges
 }
 
+// Distance as the judge computes it: the Euclidean distance floored to an integer.
+fn judge_dist(x: &[usize], y: &[usize], a: usize, b: usize) -> usize {
+    let dx = x[a] as i64 - x[b] as i64;
+    let dy = y[a] as i64 - y[b] as i64;
+    ((dx * dx + dy * dy) as f64).sqrt().floor() as usize
+}
+
+// MST of the given cities, computed exactly as the judge answers a query:
+// cities are sorted, pairs are ordered by (floored distance, local index, local index),
+// and the resulting edges are sorted.
+// Given the true coordinates, this reproduces the judge's answer bit-for-bit.
+fn local_mst(cities: &[usize], x: &[usize], y: &[usize]) -> Vec<(usize, usize)> {
+    let mut cities = cities.to_vec();
+    cities.sort_unstable();
+    let k = cities.len();
+    let mut cand = Vec::with_capacity(k * k.saturating_sub(1) / 2);
+    for i in 0..k {
+        for j in i + 1..k {
+            cand.push((judge_dist(x, y, cities[i], cities[j]), i, j));
+        }
+    }
+    cand.sort_unstable();
+    let mut uf = UnionFind::new(k);
+    let mut edges = Vec::with_capacity(k.saturating_sub(1));
+    for (_, i, j) in cand {
+        if uf.is_same_set(i, j) {
+            continue;
+        }
+        uf.unite(i, j);
+        edges.push((cities[i], cities[j]));
+    }
+    edges.sort_unstable();
+    edges
+}
+
+// Delaunay triangulation by incremental insertion (Bowyer-Watson).
+// Each point is located by walking from the last created triangle, and its cavity is found
+// by a search over triangle adjacency, so the whole triangulation takes O(n log n) expected
+// time for points inserted in a spatially coherent order. Predicates are exact.
+// Returns the undirected edges between the given points; duplicated points are linked to
+// their first copy. Convex hull edges, which a finite super triangle may drop, are added too.
+fn delaunay_edges(pts: &[(i64, i64)]) -> Vec<(usize, usize)> {
+    const NIL: usize = usize::MAX;
+    const BIG: i64 = 100_000_000;
+    let n = pts.len();
+    let mut edges = vec![];
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_unstable_by_key(|&i| pts[i]);
+    let mut uniq: Vec<usize> = vec![];
+    for &i in &order {
+        match uniq.last() {
+            Some(&j) if pts[j] == pts[i] => edges.push((j, i)),
+            _ => uniq.push(i),
+        }
+    }
+    if uniq.len() <= 1 {
+        return edges;
+    }
+    // Convex hull by monotone chain; uniq is sorted by (x, y).
+    let orient = |a: (i64, i64), b: (i64, i64), c: (i64, i64)| -> i128 {
+        (b.0 - a.0) as i128 * (c.1 - a.1) as i128 - (b.1 - a.1) as i128 * (c.0 - a.0) as i128
+    };
+    for pass in 0..2 {
+        let mut hull: Vec<usize> = vec![];
+        let seq: Vec<usize> = if pass == 0 { uniq.clone() } else { uniq.iter().rev().cloned().collect() };
+        for &i in &seq {
+            while hull.len() >= 2 && orient(pts[hull[hull.len() - 2]], pts[hull[hull.len() - 1]], pts[i]) <= 0 {
+                hull.pop();
+            }
+            hull.push(i);
+        }
+        for w in hull.windows(2) {
+            edges.push((w[0], w[1]));
+        }
+    }
+    // Points n, n + 1 and n + 2 form a super triangle far outside [0, 10000]^2.
+    let mut p: Vec<(i64, i64)> = pts.to_vec();
+    p.extend([(-BIG, -BIG), (3 * BIG, -BIG), (-BIG, 3 * BIG)]);
+    let incircle = |a: (i64, i64), b: (i64, i64), c: (i64, i64), d: (i64, i64)| -> i128 {
+        let (adx, ady) = ((a.0 - d.0) as i128, (a.1 - d.1) as i128);
+        let (bdx, bdy) = ((b.0 - d.0) as i128, (b.1 - d.1) as i128);
+        let (cdx, cdy) = ((c.0 - d.0) as i128, (c.1 - d.1) as i128);
+        let (ad, bd, cd) = (adx * adx + ady * ady, bdx * bdx + bdy * bdy, cdx * cdx + cdy * cdy);
+        adx * (bdy * cd - bd * cdy) - ady * (bdx * cd - bd * cdx) + ad * (bdx * cdy - bdy * cdx)
+    };
+    // Triangle t has counter-clockwise vertices tri[t] and nb[t][k] is across from tri[t][k].
+    let mut tri: Vec<[usize; 3]> = vec![[n, n + 1, n + 2]];
+    let mut nb: Vec<[usize; 3]> = vec![[NIL; 3]];
+    let mut mark: Vec<usize> = vec![NIL];
+    let mut alive: Vec<bool> = vec![true];
+    let mut by_first = vec![NIL; n + 3];
+    let mut by_second = vec![NIL; n + 3];
+    // Insert in a snake order over vertical strips to keep the walks short.
+    let strips = ((uniq.len() as f64).sqrt() as i64 / 2).max(1);
+    let strip_width = 10001 / strips + 1;
+    let mut ins = uniq.clone();
+    ins.sort_unstable_by_key(|&i| {
+        let s = pts[i].0 / strip_width;
+        (s, if s % 2 == 0 { pts[i].1 } else { -pts[i].1 })
+    });
+    let mut last = 0;
+    for (step, &v) in ins.iter().enumerate() {
+        let q = p[v];
+        // Locate a triangle containing q.
+        let mut t = last;
+        let mut walked = 0;
+        'walk: loop {
+            walked += 1;
+            if walked > tri.len() {
+                t = (0..tri.len())
+                    .find(|&t| alive[t] && (0..3).all(|k| orient(p[tri[t][(k + 1) % 3]], p[tri[t][(k + 2) % 3]], q) >= 0))
+                    .unwrap();
+                break;
+            }
+            for k in 0..3 {
+                if orient(p[tri[t][(k + 1) % 3]], p[tri[t][(k + 2) % 3]], q) < 0 {
+                    t = nb[t][k];
+                    continue 'walk;
+                }
+            }
+            break;
+        }
+        // Cavity: triangles whose circumcircle strictly contains q.
+        let mut cavity = vec![t];
+        mark[t] = step;
+        let mut head = 0;
+        while head < cavity.len() {
+            let c = cavity[head];
+            head += 1;
+            for k in 0..3 {
+                let d = nb[c][k];
+                if d != NIL && mark[d] != step {
+                    let [a, b, e] = tri[d];
+                    if incircle(p[a], p[b], p[e], q) > 0 {
+                        mark[d] = step;
+                        cavity.push(d);
+                    }
+                }
+            }
+        }
+        // Fan the cavity boundary around q.
+        let mut created = vec![];
+        for &c in &cavity {
+            alive[c] = false;
+            for k in 0..3 {
+                let d = nb[c][k];
+                if d != NIL && mark[d] == step {
+                    continue;
+                }
+                let (a, b) = (tri[c][(k + 1) % 3], tri[c][(k + 2) % 3]);
+                let id = tri.len();
+                tri.push([a, b, v]);
+                nb.push([NIL, NIL, d]);
+                mark.push(NIL);
+                alive.push(true);
+                if d != NIL {
+                    for slot in nb[d].iter_mut() {
+                        if *slot == c {
+                            *slot = id;
+                        }
+                    }
+                }
+                by_first[a] = id;
+                by_second[b] = id;
+                created.push(id);
+            }
+        }
+        for &id in &created {
+            let [a, b, _] = tri[id];
+            nb[id][0] = by_first[b];
+            nb[id][1] = by_second[a];
+        }
+        last = created[0];
+    }
+    for t in 0..tri.len() {
+        if !alive[t] {
+            continue;
+        }
+        for k in 0..3 {
+            let (a, b) = (tri[t][k], tri[t][(k + 1) % 3]);
+            if a < b && b < n {
+                edges.push((a, b));
+            }
+        }
+    }
+    edges
+}
+
+// Euclidean MST of the given cities over their Delaunay edges, with its length.
+// Falls back to all pairs in the unlikely case the candidates do not span the group.
+fn delaunay_mst(cities: &[usize], x: &[usize], y: &[usize]) -> (f64, Vec<(usize, usize)>) {
+    let k = cities.len();
+    let pts: Vec<(i64, i64)> = cities.iter().map(|&c| (x[c] as i64, y[c] as i64)).collect();
+    let sq = |i: usize, j: usize| (pts[i].0 - pts[j].0).pow(2) + (pts[i].1 - pts[j].1).pow(2);
+    let mut cand: Vec<(i64, usize, usize)> = delaunay_edges(&pts).into_iter().map(|(i, j)| (sq(i, j), i, j)).collect();
+    for attempt in 0..2 {
+        if attempt == 1 {
+            cand = (0..k).flat_map(|i| (i + 1..k).map(move |j| (i, j))).map(|(i, j)| (sq(i, j), i, j)).collect();
+        }
+        cand.sort_unstable();
+        let mut uf = UnionFind::new(k);
+        let mut cost = 0.0;
+        let mut edges = Vec::with_capacity(k.saturating_sub(1));
+        for &(d, i, j) in &cand {
+            if uf.is_same_set(i, j) {
+                continue;
+            }
+            uf.unite(i, j);
+            cost += (d as f64).sqrt();
+            edges.push((cities[i], cities[j]));
+        }
+        if edges.len() + 1 >= k {
+            return (cost, edges);
+        }
+    }
+    unreachable!()
+}
+
 fn find_edges_by_uf(
     groups: &[Vec<usize>], x: &[usize], y: &[usize],
     _l: usize,
 ) -> Vec<Vec<(usize, usize)>> {
+    groups.iter().map(|group| delaunay_mst(group, x, y).1).collect()
+}
+
+// Smallest and largest possible distance between a point in rectangle a and a point in rectangle b.
+fn rect_dist_range(input: &Input, a: usize, b: usize) -> (f64, f64) {
+    let Input { ref lx, ref rx, ref ly, ref ry, .. } = *input;
+    let gap = |la: usize, ra: usize, lb: usize, rb: usize| la.max(lb).saturating_sub(ra.min(rb)) as f64;
+    // The farthest pair on one axis puts each point at the end away from the other interval.
+    let span = |la: usize, ra: usize, lb: usize, rb: usize| (ra as f64 - lb as f64).max(rb as f64 - la as f64);
+    let gx = gap(lx[a], rx[a], lx[b], rx[b]);
+    let gy = gap(ly[a], ry[a], ly[b], ry[b]);
+    let sx = span(lx[a], rx[a], lx[b], rx[b]);
+    let sy = span(ly[a], ry[a], ly[b], ry[b]);
+    let range = ((gx * gx + gy * gy).sqrt(), (sx * sx + sy * sy).sqrt());
+    // The distance is convex in both points, so the maximum is attained at a pair of corners.
+    debug_assert!({
+        let corners = |c: usize| [(lx[c], ly[c]), (lx[c], ry[c]), (rx[c], ly[c]), (rx[c], ry[c])];
+        let far = corners(a).iter().flat_map(|&(xa, ya)| corners(b).map(|(xb, yb)| {
+            ((xa as f64 - xb as f64).powi(2) + (ya as f64 - yb as f64).powi(2)).sqrt()
+        })).fold(0.0, f64::max);
+        (far - range.1).abs() < 1e-6
+    }, "rect_dist_range: wrong upper bound for {a} {b}");
+    range
+}
+
+// Spanning trees that are robust to the position uncertainty given by the rectangles.
+// Each pair is weighted by the midpoint of its possible distance interval; the MST under
+// these weights is a 2-approximation of the minimax-regret spanning tree
+// (Kasperski and Zieliński, 2006).
+fn find_edges_by_robust(input: &Input, groups: &[Vec<usize>]) -> Vec<Vec<(usize, usize)>> {
     let mut edges = Vec::new();
-    let mut uf = UnionFind::new(x.len());
     for group in groups {
-        let mut group_edges = Vec::new();
-        let group_size = group.len();
-        let mut sorted_edges = Vec::new();
-        for i in 0..group_size {
-            for j in i + 1..group_size {
-                let dist = (x[group[i]] as f64 - x[group[j]] as f64).powi(2) +
-                   (y[group[i]] as f64 - y[group[j]] as f64).powi(2);
-                sorted_edges.push((dist, group[i], group[j]));
-            }
-        }
-        sorted_edges.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
-        for (_, i, j) in sorted_edges {
+        let k = group.len();
+        let mut cand = Vec::with_capacity(k * k.saturating_sub(1) / 2);
+        for i in 0..k {
+            for j in i + 1..k {
+                let (lo, hi) = rect_dist_range(input, group[i], group[j]);
+                cand.push(((lo + hi) / 2.0, i, j));
+            }
+        }
+        cand.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut uf = UnionFind::new(k);
+        let mut group_edges = Vec::with_capacity(k.saturating_sub(1));
+        for (_, i, j) in cand {
             if uf.is_same_set(i, j) {
                 continue;
             }
             uf.unite(i, j);
-            group_edges.push((i, j));
+            group_edges.push((group[i], group[j]));
         }
         edges.push(group_edges);
     }
     edges
 }
 
-
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let mut conf = Conf {
-        debug: false,
-        climb0_count: CLIMB0_COUNT,
-    };
-    for arg in args.iter().skip(1) {
-        if arg == "debug" {
-            conf.debug = true;
-        } else if arg.starts_with("climb0_count=") {
-            let val = arg.split('=').nth(1).unwrap().parse::<i32>().unwrap();
-            conf.climb0_count = val;
+// Estimated edges of every group as chosen by est_edges. With EstEdges::Auto each group takes
+// whichever of its center MST and its robust tree has the smaller total expected length.
+fn find_edges_by_estimate(
+    conf: Conf, input: &Input, groups: &[Vec<usize>], x: &[usize], y: &[usize],
+) -> Vec<Vec<(usize, usize)>> {
+    match conf.est_edges {
+        EstEdges::Center => find_edges_by_uf(groups, x, y, input.l),
+        EstEdges::Robust => find_edges_by_robust(input, groups),
+        EstEdges::Auto => {
+            let expected = |edges: &[(usize, usize)]| edges.iter().map(|&(a, b)| edge_cost(input, a, b).1).sum::<f64>();
+            let center = find_edges_by_uf(groups, x, y, input.l);
+            let robust = find_edges_by_robust(input, groups);
+            let mut by_robust = 0;
+            let edges = center.into_iter().zip(robust).map(|(c, r)| {
+                if expected(&r) < expected(&c) {
+                    by_robust += 1;
+                    r
+                } else {
+                    c
+                }
+            }).collect();
+            if conf.debug {
+                eprintln!("find_edges_by_estimate: groups by robust = {by_robust} / {}", groups.len());
+            }
+            edges
         }
     }
-    let mut rng = Rng { x: 0xc0ba_e964 };
+}
 
-    let first_line = getline().trim().to_string();
-    let first_line: Vec<usize> = first_line
-        .split_whitespace()
-        .map(|x| x.parse().unwrap())
-        .collect();
-    let (n, _m, _q, l, w) = (first_line[0], first_line[1], first_line[2], first_line[3], first_line[4]);
+// Estimated length of edge (a, b) as a (lower bound, expected value) pair.
+// Both are exact when the two cities are pinned to a single point. The expected value
+// accounts for the variance of uniform positions inside the rectangles.
+fn edge_cost(input: &Input, a: usize, b: usize) -> (f64, f64) {
+    let Input { ref lx, ref rx, ref ly, ref ry, .. } = *input;
+    let ((xa, ya), (xb, yb)) = (input.center(a), input.center(b));
+    if lx[a] == rx[a] && ly[a] == ry[a] && lx[b] == rx[b] && ly[b] == ry[b] {
+        let d = judge_dist(&[xa, xb], &[ya, yb], 0, 1) as f64;
+        return (d, d);
+    }
+    let var = |l: usize, r: usize| (r - l) as f64 * (r - l) as f64 / 12.0;
+    let lower = rect_dist_range(input, a, b).0;
+    let expected = ((xa as f64 - xb as f64).powi(2) + (ya as f64 - yb as f64).powi(2)
+        + var(lx[a], rx[a]) + var(lx[b], rx[b]) + var(ly[a], ry[a]) + var(ly[b], ry[b])).sqrt();
+    (lower, expected)
+}
 
-    let g: Vec<usize> = getline().trim().to_string()
-        .split_whitespace()
-        .map(|x| x.parse().unwrap())
-        .collect();
+// Swaps leaf cities between groups while the trees stay valid: each leaf drops its only
+// edge and is attached to the nearest city of its new group. Every other edge, including
+// the ones certified by queries, is kept as is. A move takes a random leaf and the group of
+// one of its nearest neighbors, and swaps it with the best leaf of that group, if that is a
+// gain. Both the dropped and the new edges are scored by their expected length. Stops at
+// `deadline`, or after climb_leaves_patience iterations in a row without a gain.
+fn climb_leaves(
+    conf: Conf, input: &Input, index: &SpatialIndex, rng: &mut Rng,
+    groups: &mut [Vec<usize>], edges: &mut [Vec<(usize, usize)>],
+    deadline: std::time::Instant,
+) -> u64 {
+    let n = input.n;
+    let cost = |a: usize, b: usize| edge_cost(input, a, b).1;
+    let spread = |a: usize, b: usize| {
+        let (lo, hi) = rect_dist_range(input, a, b);
+        (hi - lo) / 2.0
+    };
+    let mut group_of = vec![0; n];
+    let mut deg = vec![0; n];
+    for (i, group) in groups.iter().enumerate() {
+        for &c in group {
+            group_of[c] = i;
+        }
+    }
+    for edge_set in edges.iter() {
+        for &(a, b) in edge_set {
+            deg[a] += 1;
+            deg[b] += 1;
+        }
+    }
+    // Edge index of leaf `c` in its group, with the expected length and the spread of the edge.
+    let leaf_edge = |edges: &[Vec<(usize, usize)>], g: usize, c: usize| -> Option<(usize, f64, f64)> {
+        let idx = edges[g].iter().position(|&(a, b)| a == c || b == c)?;
+        let (a, b) = edges[g][idx];
+        Some((idx, cost(a, b), spread(a, b)))
+    };
+    // Nearest city of group g other than `skip`, seen from city c.
+    let nearest = |groups: &[Vec<usize>], g: usize, c: usize, skip: usize| -> Option<(usize, f64, f64)> {
+        let mut best: Option<(usize, f64)> = None;
+        for &d in &groups[g] {
+            if d == skip {
+                continue;
+            }
+            let dist = cost(c, d);
+            if best.map_or(true, |(_, bd)| dist < bd) {
+                best = Some((d, dist));
+            }
+        }
+        best.map(|(d, dist)| (d, dist, spread(c, d)))
+    };
+    let mut iter = 0u64;
+    let mut stall = 0u64;
+    let mut gain = 0.0;
+    loop {
+        if conf.climb_leaves_iters > 0 {
+            if iter >= conf.climb_leaves_iters {
+                break;
+            }
+        } else if iter % 256 == 0 && std::time::Instant::now() >= deadline {
+            break;
+        }
+        if stall >= conf.climb_leaves_patience {
+            break;
+        }
+        iter += 1;
+        stall += 1;
+        let a = rng.next() as usize % n;
+        let near = index.knn(a);
+        if deg[a] > 1 || near.is_empty() {
+            continue;
+        }
+        let (i, j) = (group_of[a], group_of[near[rng.next() as usize % near.len()]]);
+        if i == j {
+            continue;
+        }
+        let old_a = leaf_edge(edges, i, a);
+        // The two cities of group j nearest to a, so that the partner can be skipped.
+        let mut near_a: [Option<(usize, f64)>; 2] = [None, None];
+        for &d in &groups[j] {
+            let dist = cost(a, d);
+            if near_a[0].map_or(true, |(_, bd)| dist < bd) {
+                near_a = [Some((d, dist)), near_a[0]];
+            } else if near_a[1].map_or(true, |(_, bd)| dist < bd) {
+                near_a[1] = Some((d, dist));
+            }
+        }
+        let (mut best, mut diff) = (None, -1e-9);
+        for &b in groups[j].iter().filter(|&&b| deg[b] <= 1) {
+            let old_b = leaf_edge(edges, j, b);
+            let new_b = nearest(groups, i, b, a);
+            let new_a = if near_a[0].is_some_and(|(d, _)| d == b) { near_a[1] } else { near_a[0] };
+            let new_a = new_a.map(|(d, dist)| (d, dist, spread(a, d)));
+            let (mut d, mut risk) = (0.0, 0.0);
+            for (old, new) in [(old_a, new_b), (old_b, new_a)] {
+                d += new.map_or(0.0, |(_, d, _)| d) - old.map_or(0.0, |(_, d, _)| d);
+                risk += new.map_or(0.0, |(.., s)| s) + old.map_or(0.0, |(.., s)| s);
+            }
+            if d + conf.climb_leaves_margin * risk < diff {
+                diff = d + conf.climb_leaves_margin * risk;
+                best = Some((b, d, old_b, new_b, new_a));
+            }
+        }
+        let Some((b, d, old_b, new_b, new_a)) = best else {
+            continue;
+        };
+        for (g, c, other, old, new) in [(i, a, b, old_a, new_b), (j, b, a, old_b, new_a)] {
+            if let Some((idx, ..)) = old {
+                let (p, q) = edges[g][idx];
+                let parent = if p == c { q } else { p };
+                deg[parent] -= 1;
+                edges[g].swap_remove(idx);
+            }
+            if let Some((d, ..)) = new {
+                deg[d] += 1;
+                edges[g].push((other, d));
+            }
+            deg[c] = 0;
+            let pos = groups[g].iter().position(|&e| e == c).unwrap();
+            groups[g][pos] = other;
+            group_of[other] = g;
+        }
+        deg[a] = new_a.map_or(0, |_| 1);
+        deg[b] = new_b.map_or(0, |_| 1);
+        gain -= d;
+        stall = 0;
+    }
+    if conf.debug {
+        eprintln!("climb_leaves: iter = {iter}, gain = {gain}");
+    }
+    iter
+}
 
-    let (mut lx, mut rx, mut ly, mut ry) = (vec![0; n], vec![0; n], vec![0; n], vec![0; n]);
 
-    for i in 0..n {
-        let rect: Vec<usize> = getline().trim().to_string()
-            .split_whitespace()
-            .map(|x| x.parse().unwrap())
-            .collect();
-        lx[i] = rect[0];
-        rx[i] = rect[1];
-        ly[i] = rect[2];
-        ry[i] = rect[3];
+// Last line of defense before the answer, since a single invalid group scores 0.
+// A broken partition is rebuilt by keeping the first valid occurrence of each city up to the
+// group size and filling the gaps with the missing cities; every group whose edges are not a
+// spanning tree, including the rebuilt ones, falls back to the MST over the centers.
+// Returns the number of repaired groups.
+fn repair_answer(
+    x: &[usize], y: &[usize], g: &[usize],
+    groups: &mut Vec<Vec<usize>>, edges: &mut Vec<Vec<(usize, usize)>>,
+) -> usize {
+    let n = x.len();
+    if let Err(err) = check_partition(n, g, groups) {
+        eprintln!("repair: {err}");
+        groups.resize(g.len(), vec![]);
+        let mut seen = vec![false; n];
+        for (group, &size) in groups.iter_mut().zip(g) {
+            group.retain(|&c| c < n && !std::mem::replace(&mut seen[c], true));
+            for &c in group.iter().skip(size) {
+                seen[c] = false;
+            }
+            group.truncate(size);
+        }
+        let mut missing = (0..n).filter(|&c| !seen[c]);
+        for (group, &size) in groups.iter_mut().zip(g) {
+            while group.len() < size {
+                group.push(missing.next().unwrap());
+            }
+        }
+    }
+    edges.resize(g.len(), vec![]);
+    let mut repaired = 0;
+    for (k, (group, edge_set)) in groups.iter().zip(edges.iter_mut()).enumerate() {
+        if let Err(err) = check_tree(n, group, edge_set) {
+            eprintln!("repair: group {k}: {err}");
+            *edge_set = delaunay_mst(group, x, y).1;
+            repaired += 1;
+        }
     }
+    repaired
+}
 
-    let x: Vec<usize> = lx.iter().zip(&rx).map(|(l, r)| (l + r) / 2).collect();
-    let y: Vec<usize> = ly.iter().zip(&ry).map(|(l, r)| (l + r) / 2).collect();
+fn solve(conf: Conf, oracle: &mut dyn Oracle, dump: &mut Dump, file: &str) {
+    let start = std::time::Instant::now();
+    let input = oracle.input().clone();
+    let Input { n, q, ref g, .. } = input;
 
-    let cities0: Vec<usize> = init_mo(&x, &y, w);
-    let dist0 = init_dist(&x, &y, &cities0);
-    eprintln!("dist0 = {dist0}");
-    let cities1 = init_greedy(&x, &y, w);
-    let dist1 = init_dist(&x, &y, &cities1);
-    eprintln!("dist1 = {dist1}");
-    let cities = if dist0 < dist1 {
-        cities0
-    } else {
-        cities1
-    };
+    let (x, y): (Vec<usize>, Vec<usize>) = (0..n).map(|c| input.center(c)).unzip();
+    dump.input(file, &input, &x, &y);
 
-    let mut groups = Vec::new();
-    let mut start_idx = 0;
-    for &group_size in &g {
-        groups.push(cities[start_idx..start_idx + group_size].to_vec());
-        start_idx += group_size;
+    let multistart_deadline = start + std::time::Duration::from_secs_f64(conf.multistart_time);
+    let index = SpatialIndex::new(&x, &y, conf.knn);
+    let mut rng = Rng::new(conf.seed, 1);
+    let (mut groups, starts) = multistart(conf, &input, &x, &y, &index, &mut rng, multistart_deadline);
+    dump.groups("multistart", &groups, None);
+    let mut rng = Rng::new(conf.seed, 2);
+    climb0(conf, &x, &y, &index, &mut rng, &mut groups);
+    dump.groups("climb0", &groups, None);
+
+    let edges_est = find_edges_by_estimate(conf, &input, &groups, &x, &y);
+    let budget = (q as f64 * conf.query_budget) as usize;
+    let mut oracle = DumpOracle { inner: oracle, dump };
+    let mut edges = find_edges_by_oracle(conf, &mut oracle, &input, &groups, budget.min(q), &edges_est);
+    let deadline = start + std::time::Duration::from_secs_f64(conf.time_limit);
+    let mut rng = Rng::new(conf.seed, 3);
+    let iters = climb_leaves(conf, &input, &index, &mut rng, &mut groups, &mut edges, deadline);
+    let repaired = repair_answer(&x, &y, g, &mut groups, &mut edges);
+    if conf.debug {
+        eprintln!("repaired groups = {repaired}");
+        // With the time-bounded stages pinned to their actual lengths, the run is a pure
+        // function of the input and the configuration.
+        let mut pinned = conf;
+        pinned.multistart_starts = starts;
+        pinned.climb_leaves_iters = iters;
+        eprintln!("fingerprint = {:016x}", fingerprint(&input, &pinned));
+        eprintln!("reproduce with: seed={} multistart_starts={} climb_leaves_iters={}", conf.seed, starts, iters);
     }
-    climb0(conf, &x, &y, &mut rng, &mut groups);
+    oracle.dump.groups("final", &groups, Some(&edges));
 
-    let edges_oracle = find_edges_by_oracle(&groups, &x, &y, l);
-    let edges_uf = find_edges_by_uf(&groups, &x, &y, l);
-    let edges = edges_uf;
-    let edges = edges_oracle;
+    oracle.answer(&groups, &edges);
+}
+
+// Solves every input file given by `local=<file or directory>` in this process and prints
+// one line per file: the path, the score and the number of queries, then the total score.
+fn run_local(conf: Conf, paths: &[String], dump: &mut Dump) {
+    let mut files = vec![];
+    for path in paths {
+        if std::path::Path::new(path).is_dir() {
+            let mut entries: Vec<_> = std::fs::read_dir(path).unwrap()
+                .map(|entry| entry.unwrap().path().to_string_lossy().into_owned())
+                .collect();
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    let mut total = 0;
+    for file in &files {
+        let mut oracle = LocalOracle::new(&std::fs::read_to_string(file).unwrap());
+        solve(conf, &mut oracle, dump, file);
+        match oracle.score.take().expect("no answer") {
+            Ok(score) => {
+                println!("{file} {score} {}", oracle.queries);
+                total += score;
+            }
+            Err(err) => println!("{file} 0 {} {err}", oracle.queries),
+        }
+    }
+    println!("total {total}");
+}
 
-    answer(&groups, &edges);
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (conf, rest) = load_conf(&args);
+    if conf.debug {
+        conf.dump();
+    }
+    let mut local = vec![];
+    let mut dump_path = None;
+    for arg in &rest {
+        if let Some(path) = arg.strip_prefix("local=") {
+            local.push(path.to_string());
+        } else if let Some(path) = arg.strip_prefix("dump=") {
+            dump_path = Some(path);
+        } else {
+            panic!("unknown argument: {}", arg);
+        }
+    }
+    let mut dump = Dump::new(dump_path);
+    if local.is_empty() {
+        solve(conf, &mut StdioOracle::new(), &mut dump, "-");
+    } else {
+        run_local(conf, &local, &mut dump);
+    }
 }
@@ -0,0 +1,105 @@
+#![allow(non_snake_case)]
+
+use clap::Parser;
+use tools::*;
+
+/// Checks a transcript recorded by the tester against the judge and reports its score and
+/// the time the solver spent on each exchange.
+#[derive(Parser, Debug)]
+struct Cli {
+    /// Path to the input file
+    input: String,
+    /// Path to the transcript
+    transcript: String,
+    /// Print the time spent on every query
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let read_file = |path: &str| {
+        std::fs::read_to_string(path).unwrap_or_else(|_| {
+            eprintln!("no such file: {}", path);
+            std::process::exit(1)
+        })
+    };
+    let input = parse_input(&read_file(&cli.input)).unwrap_or_else(|err| {
+        eprintln!("invalid input {}: {}", cli.input, err);
+        std::process::exit(1)
+    });
+    let transcript = Transcript::parse(&read_file(&cli.transcript)).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1)
+    });
+    let dist = build_dist_matrix(&input);
+    let lines = &transcript.lines;
+    let mut mismatches = 0;
+    let mut queries = 0;
+    let mut last_in = Some(0.0);
+    let mut think = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let (elapsed, dir, line) = &lines[i];
+        i += 1;
+        if *dir == Dir::In {
+            last_in = Some(*elapsed);
+            continue;
+        }
+        if let Some(t) = last_in.take() {
+            think.push(elapsed - t);
+        }
+        if !line.starts_with('?') {
+            continue;
+        }
+        queries += 1;
+        let edges = match do_query(&input, line, &dist) {
+            Ok((edges, _)) => edges,
+            Err(err) => {
+                println!("query {}: {}", queries - 1, err);
+                mismatches += 1;
+                continue;
+            }
+        };
+        let mut responses = vec![];
+        while responses.len() < edges.len() && i < lines.len() && lines[i].1 == Dir::In {
+            responses.push(lines[i].2.clone());
+            last_in = Some(lines[i].0);
+            i += 1;
+        }
+        let expected: Vec<String> = edges.iter().map(|(a, b)| format!("{} {}", a, b)).collect();
+        if responses != expected {
+            println!(
+                "query {}: recorded [{}], expected [{}]",
+                queries - 1,
+                responses.join(", "),
+                expected.join(", ")
+            );
+            mismatches += 1;
+        }
+        if cli.verbose {
+            println!(
+                "query {}: {:.3} ms",
+                queries - 1,
+                think.last().unwrap_or(&0.0)
+            );
+        }
+    }
+    let total: f64 = think.iter().sum();
+    let max = think.iter().cloned().fold(0.0, f64::max);
+    println!("Queries = {}", queries);
+    println!("Mismatches = {}", mismatches);
+    println!(
+        "Solver time = {:.3} ms (max {:.3} ms per exchange)",
+        total, max
+    );
+    let score = parse_output(&input, &transcript.output())
+        .and_then(|out| compute_score_details(&input, &out.outputs, &out.output_lines));
+    match score {
+        Ok((score, _)) => println!("Score = {}", score),
+        Err(err) => {
+            println!("{}", err);
+            println!("Score = 0");
+        }
+    }
+}
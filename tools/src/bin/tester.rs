@@ -1,66 +1,442 @@
 #![allow(non_snake_case)]
 
-use std::{io::Read, process::Stdio};
+use clap::{Parser, ValueEnum};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::Stdio,
+};
 use tools::*;
 
-fn main() {
-    if std::env::args().len() < 2 {
-        eprintln!("Usage: {} <command> [<args>...]", std::env::args().nth(0).unwrap());
-        return;
+#[derive(Parser, Debug)]
+struct Cli {
+    /// Write the transcript of the interaction to this file (see `Transcript` for the format)
+    #[clap(long, conflicts_with = "batch")]
+    transcript: Option<PathBuf>,
+    /// Kill the program and report TLE after this many seconds of wall-clock time
+    #[clap(long, value_name = "SECONDS")]
+    time_limit: Option<f64>,
+    /// Run the program on every input file in this directory and print a CSV row per file
+    #[clap(long, value_name = "DIR")]
+    batch: Option<PathBuf>,
+    /// Directory to write the outputs to in batch mode
+    #[clap(long, value_name = "DIR", default_value = "out")]
+    out: PathBuf,
+    /// Directory to write the standard error of the program to in batch mode
+    #[clap(long, value_name = "DIR", default_value = "err")]
+    err: PathBuf,
+    /// Keys of the `key = value` lines in the standard error to add as CSV columns in batch mode
+    /// [default: all keys seen, with the rows printed at the end]
+    #[clap(long, value_name = "KEY,...", value_delimiter = ',')]
+    metrics: Vec<String>,
+    /// Number of inputs run at the same time in batch mode [default: number of CPUs]
+    #[clap(short, long)]
+    jobs: Option<usize>,
+    /// Rectangles given to the program [default: both, or rect in batch mode]
+    #[clap(long, value_enum)]
+    mode: Option<Mode>,
+    /// Fraction of each rectangle kept around the true position in partial mode
+    #[clap(long, value_name = "FACTOR", default_value_t = 0.5)]
+    reveal: f64,
+    /// Rerun every input in batch mode with Q overridden to each of these values
+    #[clap(long, value_name = "Q,...", value_delimiter = ',', requires = "batch")]
+    sweep_q: Vec<usize>,
+    /// Rerun every input in batch mode with L overridden to each of these values
+    #[clap(long, value_name = "L,...", value_delimiter = ',', requires = "batch")]
+    sweep_l: Vec<usize>,
+    /// Command to run, followed by its arguments
+    #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// The rectangles of the input
+    Rect,
+    /// The true positions
+    Exact,
+    /// Rect, then exact
+    Both,
+    /// The rectangles shrunk around the true positions by --reveal
+    Partial,
+}
+
+impl Cli {
+    /// Runs to make, with the label their score is printed with.
+    fn runs(&self) -> Vec<(String, Reveal)> {
+        let rect = ("Score".to_owned(), Reveal::Rect);
+        let exact = ("Score for ground truth".to_owned(), Reveal::Exact);
+        match self.mode.unwrap_or(Mode::Both) {
+            Mode::Rect => vec![rect],
+            Mode::Exact => vec![exact],
+            Mode::Both => vec![rect, exact],
+            Mode::Partial => vec![(
+                format!("Score for reveal {}", self.reveal),
+                Reveal::Partial(self.reveal),
+            )],
+        }
     }
-    let (command, args) = (std::env::args().nth(1).unwrap(), std::env::args().skip(2).collect::<Vec<_>>());
-    let mut input = String::new();
-    std::io::stdin().read_to_string(&mut input).unwrap();
-    let input = parse_input(&input);
+}
 
-    let mut p = std::process::Command::new(command.clone())
-        .args(args.clone())
+fn spawn(cli: &Cli, stderr: Stdio) -> std::process::Child {
+    std::process::Command::new(&cli.command[0])
+        .args(&cli.command[1..])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(stderr)
         .spawn()
         .unwrap_or_else(|e| {
             eprintln!("failed to execute the command");
             eprintln!("{}", e);
             std::process::exit(1)
-        });
-    match exec(&mut p, true, &input, false) {
+        })
+}
+
+fn report(label: &str, run: &Run) {
+    match &run.score {
         Ok(score) => {
-            eprintln!("Score = {}", score);
+            eprintln!("{} = {}", label, score);
         }
         Err(err) => {
-            if let Ok(Some(status)) = p.try_wait() {
-                if !status.success() {
-                    std::process::exit(1);
-                }
+            if run.crashed {
+                std::process::exit(1);
             }
-            let _ = p.kill();
             eprintln!("{}", err);
-            eprintln!("Score = 0");
+            eprintln!("{} = 0", label);
         }
     }
-    let mut p = std::process::Command::new(command)
-        .args(args)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
+    eprintln!("{}", run.usage.summary());
+}
+
+fn single(cli: &Cli) {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input).unwrap();
+    let input = parse_input(&input).unwrap_or_else(|err| {
+        eprintln!("invalid input: {}", err);
+        std::process::exit(1)
+    });
+
+    for (i, (label, reveal)) in cli.runs().into_iter().enumerate() {
+        // Only the first run echoes the output and is recorded.
+        let mut p = spawn(cli, Stdio::inherit());
+        let mut transcript = Transcript::new();
+        let result = run(
+            &mut p,
+            cli.time_limit,
+            i == 0,
+            &input,
+            reveal,
+            &mut transcript,
+        );
+        if let (0, Some(path)) = (i, &cli.transcript) {
+            let mut w = std::io::BufWriter::new(std::fs::File::create(path).unwrap_or_else(|e| {
+                eprintln!("failed to create {}: {}", path.display(), e);
+                std::process::exit(1)
+            }));
+            transcript.write(&mut w).unwrap();
+        }
+        report(&label, &result);
+    }
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Extracts the `key = value` lines, where `key` is a single word, keeping the last value of each key.
+fn parse_metrics(err: &str) -> Vec<(String, String)> {
+    let mut metrics: Vec<(String, String)> = vec![];
+    for line in err.lines() {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        let word = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || c == '-';
+        if key.is_empty() || value.is_empty() || !key.chars().all(word) {
+            continue;
+        }
+        match metrics.iter_mut().find(|(k, _)| k == key) {
+            Some(metric) => metric.1 = value.to_owned(),
+            None => metrics.push((key.to_owned(), value.to_owned())),
+        }
+    }
+    metrics
+}
+
+/// Result of one input in batch mode.
+struct Row {
+    file: String,
+    input: Input,
+    score: i64,
+    queries: usize,
+    time: f64,
+    error: Option<JudgeError>,
+    metrics: Vec<(String, String)>,
+}
+
+impl Row {
+    const HEADER: &'static str = "file,N,M,Q,L,W,score,queries,time,error_kind,error";
+
+    fn header(keys: &[String]) -> String {
+        let mut header = Self::HEADER.to_owned();
+        for key in keys {
+            header += ",";
+            header += &csv_field(key);
+        }
+        header
+    }
+
+    fn csv(&self, keys: &[String]) -> String {
+        let mut row = format!(
+            "{},{},{},{},{},{},{},{},{:.3},{},{}",
+            csv_field(&self.file),
+            self.input.N,
+            self.input.M,
+            self.input.Q,
+            self.input.L,
+            self.input.W,
+            self.score,
+            self.queries,
+            self.time,
+            self.error.as_ref().map_or("", |e| e.kind()),
+            csv_field(&self.error.as_ref().map_or(String::new(), |e| e.to_string()))
+        );
+        for key in keys {
+            row += ",";
+            if let Some((_, value)) = self.metrics.iter().find(|(k, _)| k == key) {
+                row += &csv_field(value);
+            }
+        }
+        row
+    }
+}
+
+/// One run in batch mode: an input file with Q and L optionally overridden.
+struct Job {
+    path: PathBuf,
+    Q: Option<usize>,
+    L: Option<usize>,
+}
+
+impl Job {
+    /// Name of the output file: the input's, with the overrides appended to the stem.
+    fn out_name(&self) -> String {
+        let mut name = self
+            .path
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        if let Some(Q) = self.Q {
+            name += &format!("_Q{}", Q);
+        }
+        if let Some(L) = self.L {
+            name += &format!("_L{}", L);
+        }
+        name + ".txt"
+    }
+}
+
+fn batch_one(cli: &Cli, job: &Job, reveal: Reveal) -> Row {
+    let path = &job.path;
+    let file = path.file_name().unwrap().to_string_lossy().into_owned();
+    let mut input = parse_input(&std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path.display(), e);
+        std::process::exit(1)
+    }))
+    .unwrap_or_else(|err| {
+        eprintln!("invalid input {}: {}", path.display(), err);
+        std::process::exit(1)
+    });
+    input.Q = job.Q.unwrap_or(input.Q);
+    input.L = job.L.unwrap_or(input.L);
+    let mut p = spawn(cli, Stdio::piped());
+    // Read stderr concurrently so that the program never blocks on a full pipe.
+    let mut stderr = p.stderr.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut err = vec![];
+        let _ = stderr.read_to_end(&mut err);
+        String::from_utf8_lossy(&err).into_owned()
+    });
+    let mut transcript = Transcript::new();
+    let result = run(
+        &mut p,
+        cli.time_limit,
+        false,
+        &input,
+        reveal,
+        &mut transcript,
+    );
+    let err = reader.join().unwrap();
+    for (dir, contents) in [(&cli.out, transcript.output()), (&cli.err, err.clone())] {
+        let path = dir.join(job.out_name());
+        if let Err(e) = std::fs::write(&path, contents) {
+            eprintln!("failed to write {}: {}", path.display(), e);
+            std::process::exit(1)
+        }
+    }
+    let (score, error) = match result.score {
+        Ok(score) => (score, None),
+        Err(err) => (0, Some(err)),
+    };
+    Row {
+        file,
+        input,
+        score,
+        queries: transcript.queries(),
+        time: result.usage.wall,
+        error,
+        metrics: parse_metrics(&err),
+    }
+}
+
+fn batch(cli: &Cli, dir: &Path) {
+    if cli.mode == Some(Mode::Both) {
+        eprintln!("batch mode runs a single mode; use rect, exact or partial");
+        std::process::exit(1)
+    }
+    let reveal = cli.runs()[0].1;
+    let mut files = std::fs::read_dir(dir)
         .unwrap_or_else(|e| {
-            eprintln!("failed to execute the command");
-            eprintln!("{}", e);
+            eprintln!("failed to read {}: {}", dir.display(), e);
             std::process::exit(1)
-        });
-    match exec(&mut p, true, &input, true) {
-        Ok(score) => {
-            eprintln!("Score for ground truth = {}", score);
+        })
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "txt"))
+        .collect::<Vec<_>>();
+    files.sort();
+    // Every file is run once per combination of the swept values, or once as is.
+    let opt = |v: &Vec<usize>| {
+        if v.is_empty() {
+            vec![None]
+        } else {
+            v.iter().map(|&x| Some(x)).collect()
         }
-        Err(err) => {
-            if let Ok(Some(status)) = p.try_wait() {
-                if !status.success() {
-                    std::process::exit(1);
+    };
+    let mut runs = vec![];
+    for path in files {
+        for &Q in &opt(&cli.sweep_q) {
+            for &L in &opt(&cli.sweep_l) {
+                runs.push(Job {
+                    path: path.clone(),
+                    Q,
+                    L,
+                });
+            }
+        }
+    }
+    for dir in [&cli.out, &cli.err] {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("failed to create {}: {}", dir.display(), e);
+            std::process::exit(1)
+        }
+    }
+    let jobs = cli
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .max(1);
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let (tx, rx) = std::sync::mpsc::channel();
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    // Without fixed metric columns, the header depends on every row and is printed at the end.
+    let streaming = !cli.metrics.is_empty();
+    if streaming {
+        writeln!(stdout, "{}", Row::header(&cli.metrics)).unwrap();
+    }
+    let (mut total, mut errors, mut max_time) = (0, 0, 0.0f64);
+    let mut curve = std::collections::BTreeMap::new();
+    let mut kinds = std::collections::BTreeMap::new();
+    let mut rows = runs.iter().map(|_| None).collect::<Vec<Option<Row>>>();
+    std::thread::scope(|s| {
+        for _ in 0..jobs.min(runs.len()) {
+            let tx = tx.clone();
+            let (next, runs) = (&next, &runs);
+            s.spawn(move || loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                if i >= runs.len() {
+                    break;
+                }
+                let _ = tx.send((i, batch_one(cli, &runs[i], reveal)));
+            });
+        }
+        drop(tx);
+        // Rows are printed in order as soon as all earlier ones are done.
+        let mut printed = 0;
+        for (i, row) in rx {
+            rows[i] = Some(row);
+            while printed < rows.len() {
+                let row = match &rows[printed] {
+                    Some(row) => row,
+                    None => break,
+                };
+                if streaming {
+                    writeln!(stdout, "{}", row.csv(&cli.metrics)).unwrap();
+                    stdout.flush().unwrap();
+                }
+                total += row.score;
+                // Keyed by the swept values only, so that a dimension left as is sums over files.
+                let job = &runs[printed];
+                *curve.entry((job.Q, job.L)).or_insert(0) += row.score;
+                if let Some(err) = &row.error {
+                    errors += 1;
+                    *kinds.entry(err.kind()).or_insert(0) += 1;
                 }
+                max_time = max_time.max(row.time);
+                printed += 1;
+            }
+        }
+    });
+    if !streaming {
+        let mut keys: Vec<String> = vec![];
+        for (key, _) in rows.iter().flatten().flat_map(|row| &row.metrics) {
+            if !keys.contains(key) {
+                keys.push(key.clone());
             }
-            let _ = p.kill();
-            eprintln!("{}", err);
-            eprintln!("Score for ground truth = 0");
         }
+        writeln!(stdout, "{}", Row::header(&keys)).unwrap();
+        for row in rows.iter().flatten() {
+            writeln!(stdout, "{}", row.csv(&keys)).unwrap();
+        }
+    }
+    if !cli.sweep_q.is_empty() || !cli.sweep_l.is_empty() {
+        for ((Q, L), total) in curve {
+            let mut key = vec![];
+            if let Some(Q) = Q {
+                key.push(format!("Q = {}", Q));
+            }
+            if let Some(L) = L {
+                key.push(format!("L = {}", L));
+            }
+            eprintln!("Total score for {} = {}", key.join(", "), total);
+        }
+    } else {
+        eprintln!("Total score = {}", total);
+    }
+    eprintln!("Errors = {} / {}", errors, runs.len());
+    for (kind, count) in kinds {
+        eprintln!("  {} = {}", kind, count);
+    }
+    eprintln!("Max time = {:.3} s", max_time);
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if !(0.0..=1.0).contains(&cli.reveal) {
+        eprintln!("--reveal must be between 0 and 1");
+        std::process::exit(1)
+    }
+    if cli.sweep_l.iter().any(|&L| L < 2) {
+        eprintln!("--sweep-l values must be at least 2");
+        std::process::exit(1)
+    }
+    match &cli.batch {
+        Some(dir) => batch(&cli, dir),
+        None => single(&cli),
     }
 }
@@ -4,7 +4,10 @@ use tools::*;
 
 fn main() {
     if std::env::args().len() != 3 {
-        eprintln!("Usage: {} <input> <output>", std::env::args().nth(0).unwrap());
+        eprintln!(
+            "Usage: {} <input> <output or transcript>",
+            std::env::args().nth(0).unwrap()
+        );
         return;
     }
     let in_file = std::env::args().nth(1).unwrap();
@@ -17,18 +20,30 @@ fn main() {
         eprintln!("no such file: {}", out_file);
         std::process::exit(1)
     });
-    let input = parse_input(&input);
-    let out = parse_output(&input, &output);
-    let (score, err, svg) = match out {
-        Ok(out) => vis_default(&input, &out),
-        Err(err) => (0, err, String::new()),
-    };
-    if err.len() > 0 {
-        println!("{}", err);
-        println!("Score = {}", 0);
+    let input = parse_input(&input).unwrap_or_else(|err| {
+        eprintln!("invalid input {}: {}", in_file, err);
+        std::process::exit(1)
+    });
+    let output = if Transcript::is_transcript(&output) {
+        match Transcript::parse(&output) {
+            Ok(transcript) => transcript.output(),
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1)
+            }
+        }
     } else {
-        println!("Score = {}", score);
-    }
+        output
+    };
+    let (score, svg) = match parse_output(&input, &output).and_then(|out| vis_default(&input, &out))
+    {
+        Ok(result) => result,
+        Err(err) => {
+            println!("{}", err);
+            (0, String::new())
+        }
+    };
+    println!("Score = {}", score);
     let vis = format!("<html><body>{}</body></html>", svg);
     std::fs::write("vis.html", &vis).unwrap();
 }
@@ -1,7 +1,6 @@
 #![allow(non_snake_case, unused_macros)]
 
 use itertools::Itertools;
-use proconio::input;
 use rand::prelude::*;
 use std::io::Write;
 use std::io::{prelude::*, BufReader};
@@ -238,6 +237,203 @@ mod tests {
         assert!(!d.same(0, 3));
         assert_eq!(d.groups(), vec![vec![0, 1, 2], vec![3]]);
     }
+
+    /// The first 40 cities of a generated input, in three groups.
+    fn small_input(seed: u64) -> Input {
+        let mut input = gen(seed, None, None, None);
+        input.N = 40;
+        input.M = 3;
+        input.G = vec![10, 1, 29];
+        input.range.truncate(input.N);
+        input.xy.truncate(input.N);
+        input
+    }
+
+    /// A valid output for `input`: a few queries, then each group as a path over its cities.
+    fn valid_output(input: &Input) -> String {
+        let mut out = String::new();
+        for q in 0..input.Q.min(5) {
+            let l = input.L.min(input.N);
+            out += &format!("? {} {}\n", l, (0..l).map(|i| (q + i) % input.N).join(" "));
+        }
+        out += "!\n";
+        let mut start = 0;
+        for &g in &input.G {
+            out += &format!("{}\n", (start..start + g).join(" "));
+            for i in start + 1..start + g {
+                out += &format!("{} {}\n", i - 1, i);
+            }
+            start += g;
+        }
+        out
+    }
+
+    /// Parses and scores `f` as every tool does; must return rather than panic.
+    fn judge_output(input: &Input, f: &str) -> Result<i64, JudgeError> {
+        let out = parse_output(input, f)?;
+        let dist = build_dist_matrix(input);
+        for line in &out.queries {
+            do_query(input, line, &dist)?;
+        }
+        compute_score_details(input, &out.outputs, &out.output_lines).map(|(score, _)| score)
+    }
+
+    /// Applies one random edit to the lines or tokens of `f`.
+    fn mutate(rng: &mut impl Rng, f: &str) -> String {
+        let mut lines: Vec<String> = f.lines().map(|l| l.to_owned()).collect();
+        let i = rng.gen_range(0..lines.len());
+        match rng.gen_range(0..7) {
+            0 => lines.truncate(i),
+            1 => {
+                let line = lines[i].clone();
+                lines.insert(i, line);
+            }
+            2 => {
+                lines.remove(i);
+            }
+            3 => {
+                let j = rng.gen_range(0..lines.len());
+                lines.swap(i, j);
+            }
+            _ => {
+                let mut tokens: Vec<String> =
+                    lines[i].split_whitespace().map(|t| t.to_owned()).collect();
+                let garbage = [
+                    "-1",
+                    "0",
+                    "800",
+                    "10001",
+                    "x",
+                    "?",
+                    "!",
+                    "99999999999999999999",
+                ];
+                let token = garbage[rng.gen_range(0..garbage.len())].to_owned();
+                let k = rng.gen_range(0..=tokens.len());
+                if k == tokens.len() || rng.gen_bool(0.3) {
+                    tokens.insert(k, token);
+                } else if rng.gen_bool(0.5) {
+                    tokens.remove(k);
+                } else {
+                    tokens[k] = token;
+                }
+                lines[i] = tokens.join(" ");
+            }
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn comments_are_ignored_by_the_judge() {
+        let input = small_input(2);
+        let f = valid_output(&input);
+        let mut commented = vec![];
+        for line in f.lines() {
+            commented.push(line.to_owned());
+            commented.push("#c 1 red".to_owned());
+            commented.push("# anything".to_owned());
+        }
+        let commented = commented.join("\n");
+        assert_eq!(judge_output(&input, &commented), judge_output(&input, &f));
+        let out = parse_output(&input, &commented).unwrap();
+        assert_eq!(out.comments.len(), 2 * f.lines().count());
+        assert_eq!(out.comments[0].0, 1);
+
+        let parse = |line| Annotation::parse(&input, line);
+        assert_eq!(
+            parse("#c 1 red"),
+            Some(Annotation::Color(1, "red".to_owned()))
+        );
+        assert_eq!(
+            parse("#c 1 #ff8000"),
+            Some(Annotation::Color(1, "#ff8000".to_owned()))
+        );
+        assert_eq!(
+            parse("#p 3 10 10000"),
+            Some(Annotation::Position(3, 10, 10000))
+        );
+        for line in [
+            "#",
+            "#c 1",
+            "#c 40 red",
+            "#c 1 \"red\"",
+            "#p 3 10",
+            "#p 3 10 10001",
+            "#x 1 2",
+        ] {
+            assert_eq!(parse(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn parse_input_round_trips() {
+        for seed in 0..10 {
+            let input = gen(seed, None, None, None);
+            let parsed = parse_input(&input.to_string()).unwrap();
+            assert_eq!(parsed.to_string(), input.to_string());
+        }
+    }
+
+    #[test]
+    fn malformed_inputs_are_errors() {
+        let corpus = [
+            "",
+            "3 1 5 3 100",
+            "3 2 5 3 100\n1 1\n",
+            "0 0 0 2 100\n",
+            "2 1 5 3 100\n2\n0 10 0 10\n10 0 0 10\n0 0\n0 0\n",
+            "2 1 5 3 100\n2\n0 10 0 10\n0 10 0 10\n0 0\n20 0\n",
+            "2 1 5 1 100\n2\n0 10 0 10\n0 10 0 10\n0 0\n1 1\n",
+            "2 1 -5 3 100\n2\n0 10 0 10\n0 10 0 10\n0 0\n1 1\n",
+        ];
+        for f in corpus {
+            assert!(parse_input(f).is_err(), "{:?}", f);
+        }
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(0);
+        let f = gen(0, Some(3), Some(3), None).to_string();
+        for _ in 0..500 {
+            let _ = parse_input(&mutate(&mut rng, &f));
+        }
+    }
+
+    #[test]
+    fn malformed_outputs_are_errors() {
+        let input = small_input(1);
+        let f = valid_output(&input);
+        assert!(judge_output(&input, &f).is_ok());
+        let lines: Vec<&str> = f.lines().collect();
+        // Truncated or oversized answers.
+        for n in [0, 1, 5, 6, 7, 16, lines.len() - 1] {
+            assert!(
+                judge_output(&input, &lines[..n].join("\n")).is_err(),
+                "{}",
+                n
+            );
+        }
+        assert!(judge_output(&input, &(f.clone() + "0 1\n")).is_err());
+        assert!(judge_output(&input, &(f.clone() + "!\n")).is_err());
+        let too_many = "? 2 0 1\n".repeat(input.Q + 1) + &f;
+        let out = parse_output(&input, &too_many).unwrap();
+        assert_eq!(
+            vis_default(&input, &out).unwrap_err().kind(),
+            "TooManyQueries"
+        );
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(1);
+        for _ in 0..2000 {
+            let g = mutate(&mut rng, &f);
+            let _ = judge_output(&input, &g);
+        }
+    }
+
+    #[test]
+    fn malformed_transcripts_are_errors() {
+        let ok = Transcript::parse("# transcript v1\n0.5 in 1 2\n1.0 out ! 0\n").unwrap();
+        assert_eq!(ok.output(), "! 0\n");
+        let err = Transcript::parse("# transcript v1\n0.5 in 1 2\n1.0 sideways 3\n").unwrap_err();
+        assert_eq!(err, JudgeError::InvalidTranscript.at(3, "1.0 sideways 3"));
+        let err = Transcript::parse("x in 1 2\n").unwrap_err();
+        assert_eq!(err.kind(), "ParseError");
+    }
 }
 
 #[macro_export]
@@ -285,16 +481,44 @@ impl std::fmt::Display for Input {
     }
 }
 
-pub fn parse_input(f: &str) -> Input {
-    let f = proconio::source::once::OnceSource::from(f);
-    input! {
-        from f,
-        N: usize, M: usize, Q: usize, L: usize, W: usize,
-        G: [usize; M],
-        range: [(usize, usize, usize, usize); N],
-        xy: [(usize, usize); N],
+/// Parses and validates an input file.
+/// Besides the format, it checks that the group sizes add up to `N` and that every true position
+/// lies in its rectangle within `[0, 10000]^2`.
+pub fn parse_input(f: &str) -> Result<Input, JudgeError> {
+    let mut tokens = f.lines().enumerate().flat_map(|(i, line)| {
+        line.split_whitespace()
+            .map(move |token| (i + 1, line, token))
+    });
+    let N = read_token(&mut tokens, 1..)?;
+    let M = read_token(&mut tokens, 1..=N)?;
+    let Q = read_token(&mut tokens, 0..)?;
+    let L = read_token(&mut tokens, 2..)?;
+    let W = read_token(&mut tokens, 0..)?;
+    let mut G = vec![];
+    for _ in 0..M {
+        G.push(read_token(&mut tokens, 1..=N)?);
     }
-    Input {
+    if G.iter().sum::<usize>() != N {
+        return Err(JudgeError::InvalidInput(format!(
+            "Sum of group sizes is not {}",
+            N
+        )));
+    }
+    let mut range = vec![];
+    for _ in 0..N {
+        let lx = read_token(&mut tokens, 0..=10000)?;
+        let rx = read_token(&mut tokens, lx..=10000)?;
+        let ly = read_token(&mut tokens, 0..=10000)?;
+        let ry = read_token(&mut tokens, ly..=10000)?;
+        range.push((lx, rx, ly, ry));
+    }
+    let mut xy = vec![];
+    for &(lx, rx, ly, ry) in &range {
+        let x = read_token(&mut tokens, lx..=rx)?;
+        let y = read_token(&mut tokens, ly..=ry)?;
+        xy.push((x, y));
+    }
+    Ok(Input {
         N,
         M,
         Q,
@@ -303,58 +527,130 @@ pub fn parse_input(f: &str) -> Input {
         G,
         range,
         xy,
+    })
+}
+
+/// [`read`] for the next of `tokens`, given with their line numbers and lines.
+fn read_token<'a, T, R>(
+    tokens: &mut impl Iterator<Item = (usize, &'a str, &'a str)>,
+    range: R,
+) -> Result<T, JudgeError>
+where
+    T: Copy + PartialOrd + std::fmt::Display + std::str::FromStr,
+    R: RangeBounds<T>,
+{
+    match tokens.next() {
+        Some((line, text, token)) => read(Some(token), range).map_err(|e| e.at(line, text)),
+        None => Err(JudgeError::UnexpectedEof),
     }
 }
 
 pub fn read<T: Copy + PartialOrd + std::fmt::Display + std::str::FromStr, R: RangeBounds<T>>(
     token: Option<&str>,
     range: R,
-) -> Result<T, String> {
+) -> Result<T, JudgeError> {
     if let Some(v) = token {
         if let Ok(v) = v.parse::<T>() {
             if !range.contains(&v) {
-                Err(format!("Out of range: {}", v))
+                Err(JudgeError::OutOfRange(v.to_string()))
             } else {
                 Ok(v)
             }
         } else {
-            Err(format!("Parse error: {}", v))
+            Err(JudgeError::ParseError(v.to_owned()))
         }
     } else {
-        Err("Unexpected EOF".to_owned())
+        Err(JudgeError::UnexpectedEof)
     }
 }
 
 pub struct Output {
     pub queries: Vec<String>,
     pub outputs: Vec<String>,
+    /// Comment lines (starting with `#`), each with the number of queries before it.
+    pub comments: Vec<(usize, String)>,
+    /// Line number of each query in the output file.
+    pub query_lines: Vec<usize>,
+    /// Line number of each line of `outputs` in the output file.
+    pub output_lines: Vec<usize>,
+}
+
+/// Annotation printed by a solver as a comment line, for `vis` to draw.
+/// Comment lines start with `#`; the judge ignores them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Annotation {
+    /// `#c <city> <color>`: draws the city in `color`, an svg color such as `red` or `#ff8000`.
+    Color(usize, String),
+    /// `#p <city> <x> <y>`: an estimated position of the city.
+    Position(usize, usize, usize),
 }
 
-pub fn parse_output(_input: &Input, f: &str) -> Result<Output, String> {
+impl Annotation {
+    /// Parses a comment line; returns `None` for other or malformed comments.
+    pub fn parse(input: &Input, line: &str) -> Option<Annotation> {
+        let mut tokens = line.split_whitespace();
+        let kind = tokens.next()?;
+        let i = read(tokens.next(), 0..input.N).ok()?;
+        let annotation = match kind {
+            "#c" => {
+                let color = tokens.next()?;
+                if !color.chars().all(|c| c.is_ascii_alphanumeric() || c == '#') {
+                    return None;
+                }
+                Annotation::Color(i, color.to_owned())
+            }
+            "#p" => {
+                let x = read(tokens.next(), 0..=10000).ok()?;
+                let y = read(tokens.next(), 0..=10000).ok()?;
+                Annotation::Position(i, x, y)
+            }
+            _ => return None,
+        };
+        if tokens.next().is_some() {
+            return None;
+        }
+        Some(annotation)
+    }
+}
+
+pub fn parse_output(_input: &Input, f: &str) -> Result<Output, JudgeError> {
     let mut queries = vec![];
     let mut outputs = vec![];
+    let mut comments = vec![];
+    let mut query_lines = vec![];
+    let mut output_lines = vec![];
     let mut after_output = false;
-    for line in f.lines() {
+    for (i, line) in f.lines().enumerate() {
         let line = line.trim();
         if line.len() == 0 {
             continue;
+        } else if line.starts_with('#') {
+            comments.push((queries.len(), line.to_owned()));
         } else if after_output {
             outputs.push(line.to_owned());
+            output_lines.push(i + 1);
         } else if line.starts_with('?') {
             // query
             queries.push(line.to_owned());
+            query_lines.push(i + 1);
         } else if line.starts_with('!') {
             // output
             if line != "!" {
-                return Err(format!("Illegal output format: {}", line));
+                return Err(JudgeError::IllegalFormat.at(i + 1, line));
             }
             after_output = true;
         } else {
-            return Err(format!("Unknown line: {}", line));
+            return Err(JudgeError::UnknownLine.at(i + 1, line));
         }
     }
 
-    Ok(Output { queries, outputs })
+    Ok(Output {
+        queries,
+        outputs,
+        comments,
+        query_lines,
+        output_lines,
+    })
 }
 
 pub fn gen(seed: u64, fixM: Option<usize>, fixL: Option<usize>, fixW: Option<usize>) -> Input {
@@ -432,96 +728,148 @@ pub fn gen(seed: u64, fixM: Option<usize>, fixL: Option<usize>, fixW: Option<usi
     }
 }
 
-pub fn compute_score(input: &Input, outs: &Vec<String>) -> (i64, String) {
-    let (mut score, err, _) = compute_score_details(input, &outs);
-    if err.len() > 0 {
-        score = 0;
-    }
-    (score, err)
+pub fn compute_score(input: &Input, outs: &Vec<String>) -> Result<i64, JudgeError> {
+    compute_score_details(input, outs, &[]).map(|(score, _)| score)
 }
 
+/// Parses, validates and scores the lines after `!`.
+/// `lines` holds their line numbers for error messages, and may be empty.
 pub fn compute_score_details(
     input: &Input,
-    outs: &Vec<String>,
-) -> (i64, String, (Vec<Vec<usize>>, Vec<Vec<(usize, usize)>>)) {
+    outs: &[String],
+    lines: &[usize],
+) -> Result<(i64, Answer), JudgeError> {
+    let (groups, edges) = parse_answer(input, outs, lines)?;
     let dist = build_dist_matrix(input);
-    //各グループについての回答をM回受け取り、バリデーションとスコアの計算を行う
-    let mut city_group = vec![-1; input.N];
-    let mut city_id = vec![-1; input.N];
+    let score = check_answer_at(input, &dist, &groups, &edges).map_err(|(err, k)| {
+        let line_no = lines.get(k).copied().unwrap_or(k + 1);
+        err.at(line_no, &outs[k])
+    })?;
+    Ok((score, (groups, edges)))
+}
+
+/// Cities and edges of each group in an answer.
+pub type Answer = (Vec<Vec<usize>>, Vec<Vec<(usize, usize)>>);
+
+/// Parses the lines after `!` into the cities and the edges of each group.
+/// Only the format is checked here; see [`check_answer`] for the rest.
+/// Errors are tagged with the line numbers in `lines`, or the index in `outs` counting from 1 if
+/// it is shorter.
+pub fn parse_answer(input: &Input, outs: &[String], lines: &[usize]) -> Result<Answer, JudgeError> {
     let mut line_ptr = 0;
-    let mut score = 0 as i64;
     let mut groups = vec![];
     let mut edges = vec![];
     for g in 0..(input.M) {
         groups.push(vec![]);
         edges.push(vec![]);
-        let line = &outs[line_ptr];
+        let line_no = lines.get(line_ptr).copied().unwrap_or(line_ptr + 1);
+        let line = outs.get(line_ptr).ok_or(JudgeError::UnexpectedEof)?;
         line_ptr += 1;
         let mut tokens = line.split_whitespace();
-        for k in 0..input.G[g] {
-            let i = read(tokens.next(), 0..input.N);
-            let i = match i {
-                Ok(i) => i,
-                Err(err) => return (0, err, (vec![], vec![])),
-            };
-            if city_group[i] != -1 {
-                return (
-                    0,
-                    format!("Item {} appears multiple times.", i),
-                    (vec![], vec![]),
-                );
-            }
-            city_group[i] = g as i32;
-            city_id[i] = k as i32;
+        for _ in 0..input.G[g] {
+            let i = read(tokens.next(), 0..input.N).map_err(|e| e.at(line_no, line))?;
             groups[g].push(i);
         }
         if tokens.next().is_some() {
-            return (
-                0,
-                format!("Illegal output format: {}", line),
-                (vec![], vec![]),
-            );
+            return Err(JudgeError::IllegalFormat.at(line_no, line));
         }
-
-        let mut dsu = Dsu::new(input.G[g]);
         for _ in 0..(input.G[g] - 1) {
-            let line = &outs[line_ptr];
+            let line_no = lines.get(line_ptr).copied().unwrap_or(line_ptr + 1);
+            let line = outs.get(line_ptr).ok_or(JudgeError::UnexpectedEof)?;
             line_ptr += 1;
             let mut tokens = line.split_whitespace();
-            let i = read(tokens.next(), 0..input.N);
-            let i = match i {
-                Ok(i) => i,
-                Err(err) => return (0, err, (vec![], vec![])),
-            };
-            let j = read(tokens.next(), 0..input.N);
-            let j = match j {
-                Ok(j) => j,
-                Err(err) => return (0, err, (vec![], vec![])),
-            };
+            let i = read(tokens.next(), 0..input.N).map_err(|e| e.at(line_no, line))?;
+            let j = read(tokens.next(), 0..input.N).map_err(|e| e.at(line_no, line))?;
             if tokens.next().is_some() {
-                return (
-                    0,
-                    format!("Illegal output format: {}", line),
-                    (vec![], vec![]),
-                );
+                return Err(JudgeError::IllegalFormat.at(line_no, line));
+            }
+            edges[g].push((i, j));
+        }
+    }
+    if line_ptr != outs.len() {
+        let line_no = lines.get(line_ptr).copied().unwrap_or(line_ptr + 1);
+        return Err(JudgeError::TooManyOutputs.at(line_no, &outs[line_ptr]));
+    }
+    Ok((groups, edges))
+}
+
+/// Validates an answer and returns its score: the total length of the edges, measured with `dist`.
+/// Each group `g` must consist of `G[g]` distinct cities not used by other groups, and its edges
+/// must form a spanning tree of them.
+pub fn check_answer(
+    input: &Input,
+    dist: &[Vec<usize>],
+    groups: &[Vec<usize>],
+    edges: &[Vec<(usize, usize)>],
+) -> Result<i64, JudgeError> {
+    if groups.len() != input.M || edges.len() != input.M {
+        return Err(JudgeError::WrongGroupCount {
+            expected: input.M,
+            actual: groups.len().min(edges.len()),
+        });
+    }
+    for g in 0..input.M {
+        if groups[g].len() != input.G[g] {
+            return Err(JudgeError::WrongGroupSize {
+                group: g,
+                expected: input.G[g],
+                actual: groups[g].len(),
+            });
+        }
+        if edges[g].len() + 1 != input.G[g] {
+            return Err(JudgeError::WrongEdgeCount {
+                group: g,
+                expected: input.G[g] - 1,
+                actual: edges[g].len(),
+            });
+        }
+    }
+    check_answer_at(input, dist, groups, edges).map_err(|(err, _)| err)
+}
+
+/// [`check_answer`] for an answer of the right shape, which also returns the index of the
+/// offending line among the lines after `!` on error.
+fn check_answer_at(
+    input: &Input,
+    dist: &[Vec<usize>],
+    groups: &[Vec<usize>],
+    edges: &[Vec<(usize, usize)>],
+) -> Result<i64, (JudgeError, usize)> {
+    let mut city_group = vec![-1; input.N];
+    let mut city_id = vec![-1; input.N];
+    let mut score = 0 as i64;
+    let mut line = 0;
+    for g in 0..input.M {
+        for (k, &i) in groups[g].iter().enumerate() {
+            if i >= input.N {
+                return Err((JudgeError::OutOfRange(i.to_string()), line));
+            }
+            if city_group[i] != -1 {
+                return Err((JudgeError::DuplicateCity(i), line));
+            }
+            city_group[i] = g as i32;
+            city_id[i] = k as i32;
+        }
+        let mut dsu = Dsu::new(input.G[g]);
+        for &(i, j) in &edges[g] {
+            line += 1;
+            if i >= input.N || j >= input.N {
+                return Err((JudgeError::OutOfRange(i.max(j).to_string()), line));
             }
             if city_group[i] != g as i32 || city_group[j] != g as i32 {
-                return (0, format!("Invalid edge: {} {}", i, j), (vec![], vec![]));
+                return Err((JudgeError::InvalidEdge(i, j), line));
             }
             let id_i = city_id[i] as usize;
             let id_j = city_id[j] as usize;
             if dsu.same(id_i, id_j) {
-                return (0, format!("Invalid edge: {} {}", i, j), (vec![], vec![]));
+                return Err((JudgeError::CycleInGroup(i, j), line));
             }
             dsu.merge(id_i, id_j);
             score += dist[i][j] as i64;
-            edges[g].push((i, j));
         }
+        line += 1;
     }
-    if line_ptr != outs.len() {
-        return (0, "Too many outputs".to_owned(), (vec![], vec![]));
-    }
-    (score, "".to_owned(), (groups, edges))
+    Ok(score)
 }
 
 /// 0 <= val <= 1
@@ -567,51 +915,49 @@ pub fn build_dist_matrix(input: &Input) -> Vec<Vec<usize>> {
     dist
 }
 
-pub fn get_query_from_line_and_validate(input: &Input, line: &String) -> (Vec<usize>, String) {
+/// Parses a query line `? l c_1 ... c_l` into its cities, sorted.
+pub fn get_query_from_line_and_validate(
+    input: &Input,
+    line: &str,
+) -> Result<Vec<usize>, JudgeError> {
     let mut tokens = line.split_whitespace();
-    let c = read(tokens.next(), '!'..='?');
-    let c = match c {
-        Ok(c) => c,
-        Err(err) => return (vec![], err),
-    };
+    let c = read(tokens.next(), '!'..='?')?;
     if c != '?' {
-        return (vec![], format!("Unknown char: {}", c));
+        return Err(JudgeError::UnknownChar(c));
     }
-    let l = read(tokens.next(), 2..=input.L);
-    let l = match l {
-        Ok(l) => l,
-        Err(err) => return (vec![], err),
-    };
+    let l = read(tokens.next(), 2..=input.L)?;
     let mut query = vec![];
     let mut used = vec![false; input.N];
     for _ in 0..l {
-        let i = read(tokens.next(), 0..input.N);
-        let i = match i {
-            Ok(i) => i,
-            Err(err) => return (vec![], err),
-        };
+        let i = read(tokens.next(), 0..input.N)?;
         if !used[i].setmax(true) {
-            return (vec![], format!("Item {} appears multiple times.", i));
+            return Err(JudgeError::DuplicateCity(i));
         }
         query.push(i);
     }
     if tokens.next().is_some() {
-        return (vec![], format!("Illegal output format: {}", line));
+        return Err(JudgeError::IllegalFormat);
     }
     query.sort();
-    (query, "".to_owned())
+    Ok(query)
 }
 
+/// Answers a query line: returns the edges of the minimum spanning tree and the sorted cities.
 pub fn do_query(
     input: &Input,
-    line: &String,
-    dist: &Vec<Vec<usize>>,
-) -> (Vec<(usize, usize)>, String, Vec<usize>) {
-    let (query, err) = get_query_from_line_and_validate(input, line);
-    if err.len() > 0 {
-        return (vec![], err, vec![]);
-    }
+    line: &str,
+    dist: &[Vec<usize>],
+) -> Result<(Vec<(usize, usize)>, Vec<usize>), JudgeError> {
+    let query = get_query_from_line_and_validate(input, line)?;
+    let edges = query_mst(dist, &query);
+    Ok((edges, query))
+}
 
+/// Minimum spanning tree of the cities in `query`, as returned by the judge: sorted edges
+/// `(i, j)` with `i < j`, where ties in length are broken by the position in the sorted query.
+pub fn query_mst(dist: &[Vec<usize>], query: &[usize]) -> Vec<(usize, usize)> {
+    let mut query = query.to_vec();
+    query.sort();
     let mut edges_candidate = vec![];
     for i in 0..query.len() {
         for j in i + 1..query.len() {
@@ -629,7 +975,238 @@ pub fn do_query(
         edges.push((query[i], query[j]));
     }
     edges.sort();
-    (edges, "".to_owned(), query)
+    edges
+}
+
+/// Error reported by the judge.
+///
+/// Errors on a line of the solver's output are wrapped in [`JudgeError::Line`], which gives the
+/// line number (counting from 1) and its text; [`JudgeError::kind`] sees through it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JudgeError {
+    /// The output ended in the middle of a line or of the answer.
+    UnexpectedEof,
+    /// A token that is not a number.
+    ParseError(String),
+    /// A number out of its allowed range (a city, or the size of a query).
+    OutOfRange(String),
+    /// A line starting with a character other than `?` or `!`.
+    UnknownChar(char),
+    /// Extra tokens, or a `!` line with something after it.
+    IllegalFormat,
+    /// A line that is neither a query nor an answer.
+    UnknownLine,
+    /// More than `Q` queries.
+    TooManyQueries {
+        limit: usize,
+    },
+    /// A city appearing twice in a query or in the answer.
+    DuplicateCity(usize),
+    /// An edge whose ends are not both in its group.
+    InvalidEdge(usize, usize),
+    /// An edge between two cities already connected in their group.
+    CycleInGroup(usize, usize),
+    WrongGroupCount {
+        expected: usize,
+        actual: usize,
+    },
+    WrongGroupSize {
+        group: usize,
+        expected: usize,
+        actual: usize,
+    },
+    WrongEdgeCount {
+        group: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// Output after the answer.
+    TooManyOutputs,
+    /// The solver closed its output before finishing the answer.
+    Terminated,
+    TimeLimitExceeded {
+        seconds: f64,
+    },
+    /// An input file that violates the constraints.
+    InvalidInput(String),
+    /// A transcript line that is not `<elapsed> in|out <line>`.
+    InvalidTranscript,
+    /// `error` on line `line` of the output, whose text is `text`.
+    Line {
+        line: usize,
+        text: String,
+        error: Box<JudgeError>,
+    },
+}
+
+impl JudgeError {
+    /// Tags the error with the line it was found on.
+    pub fn at(self, line: usize, text: &str) -> Self {
+        JudgeError::Line {
+            line,
+            text: text.to_owned(),
+            error: Box::new(self),
+        }
+    }
+
+    /// The error without the line it was found on.
+    pub fn inner(&self) -> &JudgeError {
+        match self {
+            JudgeError::Line { error, .. } => error.inner(),
+            e => e,
+        }
+    }
+
+    /// Name of the variant, e.g. `"DuplicateCity"`, for aggregating errors.
+    pub fn kind(&self) -> &'static str {
+        match self.inner() {
+            JudgeError::UnexpectedEof => "UnexpectedEof",
+            JudgeError::ParseError(_) => "ParseError",
+            JudgeError::OutOfRange(_) => "OutOfRange",
+            JudgeError::UnknownChar(_) => "UnknownChar",
+            JudgeError::IllegalFormat => "IllegalFormat",
+            JudgeError::UnknownLine => "UnknownLine",
+            JudgeError::TooManyQueries { .. } => "TooManyQueries",
+            JudgeError::DuplicateCity(_) => "DuplicateCity",
+            JudgeError::InvalidEdge(..) => "InvalidEdge",
+            JudgeError::CycleInGroup(..) => "CycleInGroup",
+            JudgeError::WrongGroupCount { .. } => "WrongGroupCount",
+            JudgeError::WrongGroupSize { .. } => "WrongGroupSize",
+            JudgeError::WrongEdgeCount { .. } => "WrongEdgeCount",
+            JudgeError::TooManyOutputs => "TooManyOutputs",
+            JudgeError::Terminated => "Terminated",
+            JudgeError::TimeLimitExceeded { .. } => "TimeLimitExceeded",
+            JudgeError::InvalidInput(_) => "InvalidInput",
+            JudgeError::InvalidTranscript => "InvalidTranscript",
+            JudgeError::Line { .. } => unreachable!(),
+        }
+    }
+}
+
+impl std::fmt::Display for JudgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JudgeError::UnexpectedEof => write!(f, "Unexpected EOF"),
+            JudgeError::ParseError(token) => write!(f, "Parse error: {}", token),
+            JudgeError::OutOfRange(token) => write!(f, "Out of range: {}", token),
+            JudgeError::UnknownChar(c) => write!(f, "Unknown char: {}", c),
+            JudgeError::IllegalFormat => write!(f, "Illegal output format"),
+            JudgeError::UnknownLine => write!(f, "Unknown line"),
+            JudgeError::TooManyQueries { limit } => {
+                write!(f, "Too many queries (Q = {})", limit)
+            }
+            JudgeError::DuplicateCity(i) => write!(f, "Item {} appears multiple times.", i),
+            JudgeError::InvalidEdge(i, j) => write!(f, "Invalid edge: {} {}", i, j),
+            JudgeError::CycleInGroup(i, j) => write!(f, "Invalid edge (cycle): {} {}", i, j),
+            JudgeError::WrongGroupCount { expected, actual } => {
+                write!(
+                    f,
+                    "Wrong number of groups: {} (expected {})",
+                    actual, expected
+                )
+            }
+            JudgeError::WrongGroupSize {
+                group,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Wrong size of group {}: {} (expected {})",
+                group, actual, expected
+            ),
+            JudgeError::WrongEdgeCount {
+                group,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Wrong number of edges in group {}: {} (expected {})",
+                group, actual, expected
+            ),
+            JudgeError::TooManyOutputs => write!(f, "Too many outputs"),
+            JudgeError::Terminated => write!(f, "Your program has terminated unexpectedly"),
+            JudgeError::TimeLimitExceeded { seconds } => {
+                write!(f, "Time limit exceeded ({:.3} s)", seconds)
+            }
+            JudgeError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            JudgeError::InvalidTranscript => write!(f, "Invalid transcript line"),
+            JudgeError::Line { line, text, error } => {
+                write!(f, "{} (line {}: {})", error, line, text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for JudgeError {}
+
+/// In-process judge for one input, for driving a solver without going through text.
+///
+/// ```
+/// use tools::*;
+/// let input = gen(0, None, None, None);
+/// let mut judge = Judge::new(input);
+/// let edges = judge.query(&[0, 1, 2]).unwrap();
+/// assert_eq!(edges.len(), 2);
+/// assert!(judge.query(&[0, 0]).is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Judge {
+    input: Input,
+    dist: Vec<Vec<usize>>,
+    queries: usize,
+}
+
+impl Judge {
+    pub fn new(input: Input) -> Self {
+        let dist = build_dist_matrix(&input);
+        Self {
+            input,
+            dist,
+            queries: 0,
+        }
+    }
+
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    /// Number of queries made so far.
+    pub fn queries(&self) -> usize {
+        self.queries
+    }
+
+    /// Returns the minimum spanning tree of `cities` (see [`query_mst`]).
+    /// Fails if the query is invalid or the `Q` queries have all been used; a failed query does
+    /// not count.
+    pub fn query(&mut self, cities: &[usize]) -> Result<Vec<(usize, usize)>, JudgeError> {
+        let input = &self.input;
+        if self.queries == input.Q {
+            return Err(JudgeError::TooManyQueries { limit: input.Q });
+        }
+        if !(2..=input.L).contains(&cities.len()) {
+            return Err(JudgeError::OutOfRange(cities.len().to_string()));
+        }
+        let mut used = vec![false; input.N];
+        for &i in cities {
+            if i >= input.N {
+                return Err(JudgeError::OutOfRange(i.to_string()));
+            }
+            if !used[i].setmax(true) {
+                return Err(JudgeError::DuplicateCity(i));
+            }
+        }
+        self.queries += 1;
+        Ok(query_mst(&self.dist, cities))
+    }
+
+    /// Scores the final answer (see [`check_answer`]).
+    pub fn answer(
+        &self,
+        groups: &[Vec<usize>],
+        edges: &[Vec<(usize, usize)>],
+    ) -> Result<i64, JudgeError> {
+        check_answer(&self.input, &self.dist, groups, edges)
+    }
 }
 
 fn rect(x: i32, y: i32, w: i32, h: i32, fill: &str, fill_opacity: f32) -> Rectangle {
@@ -719,15 +1296,17 @@ pub fn edge_group(
     )
 }
 
-pub fn vis_default(input: &Input, out: &Output) -> (i64, String, String) {
-    let (mut score, err, svg) = vis(input, out, out.queries.len(), "true");
-    if err.len() > 0 {
-        score = 0;
-    }
-    (score, err, svg)
+pub fn vis_default(input: &Input, out: &Output) -> Result<(i64, String), JudgeError> {
+    vis(input, out, out.queries.len(), "true")
 }
 
-pub fn vis(input: &Input, out: &Output, t: usize, coordinate: &str) -> (i64, String, String) {
+/// Returns the score and the svg.
+pub fn vis(
+    input: &Input,
+    out: &Output,
+    t: usize,
+    coordinate: &str,
+) -> Result<(i64, String), JudgeError> {
     let W = 800;
     let mut doc = svg::Document::new()
         .set("id", "vis")
@@ -765,23 +1344,22 @@ pub fn vis(input: &Input, out: &Output, t: usize, coordinate: &str) -> (i64, Str
     let query_size = out.queries.len();
 
     //validation
+    let line_no = |lines: &Vec<usize>, i: usize| lines.get(i).copied().unwrap_or(i + 1);
     if query_size > input.Q {
-        return (0, "Too many queries".to_owned(), String::new());
+        let line = line_no(&out.query_lines, input.Q);
+        return Err(JudgeError::TooManyQueries { limit: input.Q }.at(line, &out.queries[input.Q]));
     }
     for i in 0..query_size {
-        let (_, err) = get_query_from_line_and_validate(&input, &out.queries[i]);
-        if err.len() > 0 {
-            return (0, err, String::new());
+        if let Err(err) = get_query_from_line_and_validate(&input, &out.queries[i]) {
+            return Err(err.at(line_no(&out.query_lines, i), &out.queries[i]));
         }
     }
     let mut score = 0;
     let mut groups = vec![];
     let mut edges = vec![];
     if out.outputs.len() > 0 {
-        let (score_tmp, err, groups_and_edges) = compute_score_details(&input, &out.outputs);
-        if err.len() > 0 {
-            return (0, err, String::new());
-        }
+        let (score_tmp, groups_and_edges) =
+            compute_score_details(&input, &out.outputs, &out.output_lines)?;
         score = score_tmp;
         groups = groups_and_edges.0;
         edges = groups_and_edges.1;
@@ -790,10 +1368,8 @@ pub fn vis(input: &Input, out: &Output, t: usize, coordinate: &str) -> (i64, Str
     if t < query_size {
         //query
         let line = &out.queries[t];
-        let (edges, err, query) = do_query(&input, &line, &dist);
-        if err.len() > 0 {
-            return (0, err, String::new());
-        }
+        let (edges, query) =
+            do_query(&input, &line, &dist).map_err(|e| e.at(line_no(&out.query_lines, t), line))?;
         for (i, j) in edges {
             doc = doc.add(edge_group(
                 i, j, x[i], y[i], x[j], y[j], dist[i][j], true, -1,
@@ -873,96 +1449,471 @@ pub fn vis(input: &Input, out: &Output, t: usize, coordinate: &str) -> (i64, Str
         }
     }
 
-    (score, "".to_owned(), doc.to_string())
+    // annotations made before the query shown, the last one of each kind per city
+    let mut colors = vec![None; input.N];
+    let mut positions = vec![None; input.N];
+    for (q, line) in &out.comments {
+        if *q > t {
+            break;
+        }
+        match Annotation::parse(input, line) {
+            Some(Annotation::Color(i, color)) => colors[i] = Some(color),
+            Some(Annotation::Position(i, px, py)) => positions[i] = Some((px, py)),
+            None => (),
+        }
+    }
+    let mut annotations = Group::new().set("pointer-events", "none");
+    for i in 0..input.N {
+        if let Some((px, py)) = positions[i] {
+            annotations = annotations.add(
+                Line::new()
+                    .set("x1", px * W / 10000)
+                    .set("y1", py * W / 10000)
+                    .set("x2", x[i] * W / 10000)
+                    .set("y2", y[i] * W / 10000)
+                    .set("stroke", "gray")
+                    .set("stroke-width", 1)
+                    .set("stroke-dasharray", "2,2"),
+            );
+            annotations = annotations.add(rect(
+                (px * W / 10000) as i32 - 2,
+                (py * W / 10000) as i32 - 2,
+                4,
+                4,
+                colors[i].as_deref().unwrap_or("gray"),
+                1.0,
+            ));
+        }
+        if let Some(color) = &colors[i] {
+            annotations = annotations.add(
+                Circle::new()
+                    .set("cx", x[i] * W / 10000)
+                    .set("cy", y[i] * W / 10000)
+                    .set("r", 3)
+                    .set("fill", color.as_str()),
+            );
+        }
+    }
+    doc = doc.add(annotations);
+
+    Ok((score, doc.to_string()))
+}
+
+/// What the judge tells the solver about the position of each city.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reveal {
+    /// The rectangles of the input.
+    Rect,
+    /// Zero-width rectangles at the true positions.
+    Exact,
+    /// The rectangles shrunk around the true position, keeping this fraction of their extent on
+    /// each side (1 is the same as `Rect`, 0 is the same as `Exact`).
+    Partial(f64),
+}
+
+impl Reveal {
+    /// Rectangle `(lx, rx, ly, ry)` given to the solver for city `i`.
+    pub fn range(&self, input: &Input, i: usize) -> (usize, usize, usize, usize) {
+        let (lx, rx, ly, ry) = input.range[i];
+        let (x, y) = input.xy[i];
+        match *self {
+            Reveal::Rect => (lx, rx, ly, ry),
+            Reveal::Exact => (x, x, y, y),
+            Reveal::Partial(f) => {
+                let f = f.clamp(0.0, 1.0);
+                let shrink = |d: usize| (d as f64 * f).ceil() as usize;
+                (
+                    x - shrink(x - lx),
+                    x + shrink(rx - x),
+                    y - shrink(y - ly),
+                    y + shrink(ry - y),
+                )
+            }
+        }
+    }
+}
+
+/// Direction of a transcript line, seen from the solver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dir {
+    /// Sent to the solver's stdin.
+    In,
+    /// Read from the solver's stdout.
+    Out,
+}
+
+/// Record of an interaction between the judge and a solver.
+///
+/// The text format, written by [`Transcript::write`] and read by [`Transcript::parse`], has one
+/// line per exchanged line:
+///
+/// ```text
+/// # transcript v1
+/// <elapsed> in <line>
+/// <elapsed> out <line>
+/// ```
+///
+/// `<elapsed>` is the time since the solver started, in milliseconds.
+/// `in` lines are sent to the solver (the input and the responses to queries) and `out` lines
+/// are read from it (the queries and the answer); empty output lines are skipped.
+/// Other lines starting with `#` are comments.
+/// The time a solver spends on a query is the gap between the query and the last `in` line before it.
+#[derive(Clone, Debug)]
+pub struct Transcript {
+    start: std::time::Instant,
+    pub lines: Vec<(f64, Dir, String)>,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transcript {
+    pub const HEADER: &'static str = "# transcript v1";
+
+    /// Creates an empty transcript whose clock starts now.
+    pub fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+            lines: vec![],
+        }
+    }
+
+    pub fn record(&mut self, dir: Dir, line: &str) {
+        let elapsed = self.start.elapsed().as_secs_f64() * 1000.0;
+        self.lines.push((elapsed, dir, line.to_owned()));
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        writeln!(w, "{}", Self::HEADER)?;
+        for (elapsed, dir, line) in &self.lines {
+            let dir = match dir {
+                Dir::In => "in",
+                Dir::Out => "out",
+            };
+            writeln!(w, "{:.3} {} {}", elapsed, dir, line)?;
+        }
+        Ok(())
+    }
+
+    /// Whether `f` looks like a transcript rather than a plain output.
+    pub fn is_transcript(f: &str) -> bool {
+        f.trim_start().starts_with(Self::HEADER)
+    }
+
+    pub fn parse(f: &str) -> Result<Transcript, JudgeError> {
+        let mut lines = vec![];
+        for (i, line) in f.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(3, ' ');
+            let elapsed = read(parts.next(), 0.0..).map_err(|e| e.at(i + 1, line))?;
+            let dir = match parts.next() {
+                Some("in") => Dir::In,
+                Some("out") => Dir::Out,
+                _ => return Err(JudgeError::InvalidTranscript.at(i + 1, line)),
+            };
+            lines.push((elapsed, dir, parts.next().unwrap_or("").to_owned()));
+        }
+        Ok(Transcript {
+            start: std::time::Instant::now(),
+            lines,
+        })
+    }
+
+    /// Number of queries the solver made.
+    pub fn queries(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|(_, dir, line)| *dir == Dir::Out && line.starts_with('?'))
+            .count()
+    }
+
+    /// Everything the solver printed, in the format accepted by [`parse_output`].
+    pub fn output(&self) -> String {
+        let mut out = String::new();
+        for (_, dir, line) in &self.lines {
+            if *dir == Dir::Out {
+                out += line;
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+fn send<W: Write>(stdin: &mut W, transcript: &mut Transcript, line: String) {
+    let _ = writeln!(stdin, "{}", line);
+    transcript.record(Dir::In, &line);
 }
 
-fn read_line(stdout: &mut BufReader<ChildStdout>, local: bool, exact_value: bool) -> Result<String, String> {
+/// Reads the next non-empty line that is not a comment, counting every line read in `line_no`.
+fn read_line(
+    stdout: &mut BufReader<ChildStdout>,
+    local: bool,
+    transcript: &mut Transcript,
+    line_no: &mut usize,
+) -> Result<String, JudgeError> {
     loop {
         let mut out = String::new();
         match stdout.read_line(&mut out) {
             Ok(0) | Err(_) => {
-                return Err(format!("Your program has terminated unexpectedly"));
+                return Err(JudgeError::Terminated);
             }
             _ => (),
         }
-        if local && !exact_value {
+        if local {
             print!("{}", out);
         }
         let v = out.trim();
         if v.len() == 0 {
             continue;
         }
+        transcript.record(Dir::Out, v);
+        *line_no += 1;
+        if v.starts_with('#') {
+            continue;
+        }
         return Ok(v.to_owned());
     }
 }
 
-pub fn exec(p: &mut std::process::Child, local: bool, input: &Input, exact_value: bool) -> Result<i64, String> {
+/// Runs the interaction with the solver `p` and returns its score.
+/// The solver is given the rectangles chosen by `reveal`, and its output is echoed to stdout if
+/// `local` is set.
+/// Every exchanged line is recorded in `transcript`.
+pub fn exec(
+    p: &mut std::process::Child,
+    local: bool,
+    input: &Input,
+    reveal: Reveal,
+    transcript: &mut Transcript,
+) -> Result<i64, JudgeError> {
     let mut stdin = std::io::BufWriter::new(p.stdin.take().unwrap());
     let mut stdout = std::io::BufReader::new(p.stdout.take().unwrap());
-    let _ = writeln!(
-        stdin,
-        "{} {} {} {} {}",
-        input.N, input.M, input.Q, input.L, input.W
+    send(
+        &mut stdin,
+        transcript,
+        format!(
+            "{} {} {} {} {}",
+            input.N, input.M, input.Q, input.L, input.W
+        ),
     );
-    let _ = writeln!(stdin, "{}", input.G.iter().join(" "));
+    send(&mut stdin, transcript, input.G.iter().join(" "));
     for i in 0..input.N {
-        if exact_value {
-            let _ = writeln!(
-                stdin,
-                "{} {} {} {}",
-                input.xy[i].0, input.xy[i].0, input.xy[i].1, input.xy[i].1,
-            );
-        } else {
-            let _ = writeln!(
-                stdin,
-                "{} {} {} {}",
-                input.range[i].0, input.range[i].1, input.range[i].2, input.range[i].3,
-            );
-        }
-}
+        let (lx, rx, ly, ry) = reveal.range(input, i);
+        send(
+            &mut stdin,
+            transcript,
+            format!("{} {} {} {}", lx, rx, ly, ry),
+        );
+    }
     let _ = stdin.flush();
-    //真の座標についてユークリッド距離行列を作成する
-    let dist = build_dist_matrix(&input);
-    let mut score = 0;
-    for q in 0..(input.Q + 1) {
-        let line = read_line(&mut stdout, local, exact_value)?;
+    let mut judge = Judge::new(input.clone());
+    let mut line_no = 0;
+    let score;
+    loop {
+        let line = read_line(&mut stdout, local, transcript, &mut line_no)?;
         let mut tokens = line.split_whitespace();
-        let c = read(tokens.next(), '!'..='?')?;
+        let c = read(tokens.next(), '!'..='?').map_err(|e| e.at(line_no, &line))?;
         if c != '!' && c != '?' {
-            return Err(format!("Unknown char: {}", c));
+            return Err(JudgeError::UnknownChar(c).at(line_no, &line));
         }
         if c == '?' {
-            if q == input.Q {
-                return Err(format!("Too many queries: {}", line));
-            }
-            let (edges, err, _) = do_query(&input, &line, &dist);
-            if err.len() > 0 {
-                return Err(err);
-            }
+            let edges = get_query_from_line_and_validate(input, &line)
+                .and_then(|query| judge.query(&query))
+                .map_err(|e| e.at(line_no, &line))?;
             //辺の集合を出力
             for (i, j) in edges {
-                let _ = writeln!(stdin, "{} {}", i, j);
+                send(&mut stdin, transcript, format!("{} {}", i, j));
             }
             let _ = stdin.flush();
         } else {
             if tokens.next().is_some() {
-                return Err(format!("Illegal output format: {}", line));
+                return Err(JudgeError::IllegalFormat.at(line_no, &line));
             }
             let mut outputs = vec![];
+            let mut lines = vec![];
             for _ in 0..input.N {
-                let line = read_line(&mut stdout, local, exact_value)?;
+                let line = read_line(&mut stdout, local, transcript, &mut line_no)?;
                 outputs.push(line);
+                lines.push(line_no);
             }
-            let (score_tmp, err, _) = compute_score_details(&input, &outputs);
-            if err.len() > 0 {
-                return Err(err);
-            }
-            score = score_tmp;
+            let (groups, edges) = parse_answer(input, &outputs, &lines)?;
+            score = check_answer_at(input, &judge.dist, &groups, &edges)
+                .map_err(|(err, k)| err.at(lines[k], &outputs[k]))?;
             break;
         }
     }
-    if read_line(&mut stdout, local, exact_value).is_ok() {
-        return Err("Too many output".to_owned());
+    if let Ok(line) = read_line(&mut stdout, local, transcript, &mut line_no) {
+        return Err(JudgeError::TooManyOutputs.at(line_no, &line));
+    }
+    Ok(score)
+}
+
+/// Resources used by a finished solver process.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    /// Wall-clock time from the start of [`run`] until the process was reaped, in seconds.
+    pub wall: f64,
+    /// User plus system CPU time, in seconds (unix only).
+    pub cpu: Option<f64>,
+    /// Peak resident set size, in KiB (unix only).
+    pub max_rss: Option<u64>,
+}
+
+/// Outcome of [`run`].
+#[derive(Clone, Debug)]
+pub struct Run {
+    /// Score, or the reason the interaction failed.
+    pub score: Result<i64, JudgeError>,
+    /// Whether the solver was killed for exceeding the time limit.
+    pub tle: bool,
+    /// Whether the solver exited with a failure status on its own.
+    pub crashed: bool,
+    pub usage: Usage,
+}
+
+impl Usage {
+    /// One line summary, e.g. `time = 1.234 s, cpu = 1.200 s, memory = 12.3 MiB`.
+    pub fn summary(&self) -> String {
+        let mut s = format!("time = {:.3} s", self.wall);
+        if let Some(cpu) = self.cpu {
+            s += &format!(", cpu = {:.3} s", cpu);
+        }
+        if let Some(rss) = self.max_rss {
+            s += &format!(", memory = {:.1} MiB", rss as f64 / 1024.0);
+        }
+        s
+    }
+}
+
+/// Waits for `p` and returns whether it exited with a failure status on its own, together with
+/// its CPU time and peak RSS.
+/// If `kill` is set and the process is still running, it is killed first.
+/// `reaped` is set, under its lock, once `p` has exited but before its pid is released, so
+/// that whoever kills `p` while holding the lock never signals a recycled pid.
+#[cfg(unix)]
+fn reap(
+    p: &mut std::process::Child,
+    kill: bool,
+    reaped: &std::sync::Mutex<bool>,
+) -> (bool, Option<f64>, Option<u64>) {
+    let pid = p.id() as libc::pid_t;
+    if kill {
+        // Until it is reaped, the pid is still ours, even if the process has already exited.
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+    // Wait for the exit while leaving the process a zombie.
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    while unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOWAIT,
+        )
+    } != 0
+    {
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            break;
+        }
+    }
+    *reaped.lock().unwrap() = true;
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } != pid {
+        return (false, None, None);
+    }
+    let tv = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 * 1e-6;
+    let success = libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0;
+    let killed = kill && libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGKILL;
+    (
+        !killed && !success,
+        Some(tv(usage.ru_utime) + tv(usage.ru_stime)),
+        Some(usage.ru_maxrss as u64),
+    )
+}
+
+#[cfg(not(unix))]
+fn reap(
+    p: &mut std::process::Child,
+    kill: bool,
+    reaped: &std::sync::Mutex<bool>,
+) -> (bool, Option<f64>, Option<u64>) {
+    let ret = if let Ok(Some(status)) = p.try_wait() {
+        (!status.success(), None, None)
+    } else {
+        if kill {
+            let _ = p.kill();
+        }
+        let status = p.wait();
+        (!kill && !status.is_ok_and(|s| s.success()), None, None)
+    };
+    *reaped.lock().unwrap() = true;
+    ret
+}
+
+/// Runs [`exec`] on the freshly spawned solver `p`, killing it once `time_limit` seconds have
+/// passed, and reaps it to measure its resource usage.
+///
+/// On platforms other than unix the solver is not killed; the run is marked as TLE afterwards
+/// if it took too long.
+pub fn run(
+    p: &mut std::process::Child,
+    time_limit: Option<f64>,
+    local: bool,
+    input: &Input,
+    reveal: Reveal,
+    transcript: &mut Transcript,
+) -> Run {
+    let start = std::time::Instant::now();
+    let killed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let reaped = std::sync::Arc::new(std::sync::Mutex::new(false));
+    let (stop, stopped) = std::sync::mpsc::channel::<()>();
+    let watchdog = time_limit.map(|limit| {
+        let killed = killed.clone();
+        let reaped = reaped.clone();
+        let pid = p.id();
+        std::thread::spawn(move || {
+            let timeout = std::time::Duration::from_secs_f64(limit);
+            if let Err(std::sync::mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(timeout) {
+                // Holding the lock keeps reap from releasing the pid until the signal is sent.
+                let reaped = reaped.lock().unwrap();
+                if *reaped {
+                    return;
+                }
+                killed.store(true, std::sync::atomic::Ordering::SeqCst);
+                #[cfg(unix)]
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGKILL);
+                }
+                #[cfg(not(unix))]
+                let _ = pid;
+            }
+        })
+    });
+    let score = exec(p, local, input, reveal, transcript);
+    let (failed, cpu, max_rss) = reap(p, score.is_err(), &reaped);
+    let wall = start.elapsed().as_secs_f64();
+    let _ = stop.send(());
+    if let Some(watchdog) = watchdog {
+        let _ = watchdog.join();
+    }
+    let tle = killed.load(std::sync::atomic::Ordering::SeqCst)
+        || time_limit.is_some_and(|limit| wall > limit);
+    let score = if tle {
+        Err(JudgeError::TimeLimitExceeded { seconds: wall })
+    } else {
+        score
+    };
+    Run {
+        crashed: failed && !tle && score.is_err(),
+        score,
+        tle,
+        usage: Usage { wall, cpu, max_rss },
     }
-    Ok(score as i64)
 }